@@ -0,0 +1,434 @@
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time;
+
+use crate::events::{self, Event};
+use crate::ref_price::{FeedState, RefPrice, RefPricing};
+
+const AGGREGATION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the venues references are combined
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aggregation {
+    /// Median of the venues bids and asks
+    Median,
+    /// Average of the venues bids and asks weighted per venue
+    Weighted,
+}
+
+pub struct Venue {
+    pub name: String,
+    pub weight: f64,
+    pub rp: Arc<Mutex<RefPrice>>,
+}
+
+// a pair the aggregated reference is converted through, to quote
+// in the unit of the vega market
+pub struct CrossLeg {
+    pub name: String,
+    pub invert: bool,
+    pub rp: Arc<Mutex<RefPrice>>,
+}
+
+pub struct AggregatorConfig {
+    pub method: Aggregation,
+    // maximum relative distance of a venue mid to the median mid
+    pub max_deviation: f64,
+    // minimum number of valid venues to produce a reference
+    pub min_venues: usize,
+    pub max_price_age: Duration,
+    pub pricing: RefPricing,
+    pub pricing_volume: f64,
+}
+
+struct Quote {
+    name: String,
+    weight: f64,
+    bid: f64,
+    bid_volume: f64,
+    ask: f64,
+    ask_volume: f64,
+    event_time: u64,
+}
+
+impl Quote {
+    fn mid(&self) -> f64 {
+        return (self.bid + self.ask) / 2.;
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len();
+    if n % 2 == 0 {
+        return (values[n / 2 - 1] + values[n / 2]) / 2.;
+    }
+    return values[n / 2];
+}
+
+fn weighted(quotes: &[Quote], f: fn(&Quote) -> f64) -> f64 {
+    let total = quotes.iter().map(|q| q.weight).sum::<f64>();
+    return quotes.iter().map(|q| f(q) * q.weight).sum::<f64>() / total;
+}
+
+struct Aggregator {
+    venues: Vec<Venue>,
+    legs: Vec<CrossLeg>,
+    cfg: AggregatorConfig,
+    out: Arc<Mutex<RefPrice>>,
+    events: events::Sender,
+    // venues currently rejected as outliers, to only log changes
+    rejected: HashSet<String>,
+}
+
+impl Aggregator {
+    fn quotes(&self) -> Vec<Quote> {
+        let mut quotes = vec![];
+        for v in self.venues.iter() {
+            let rp = v.rp.lock().unwrap();
+            if rp.get_state() != FeedState::Connected || rp.is_stale(self.cfg.max_price_age) {
+                continue;
+            }
+            // venues not streaming depth only provide a top of book
+            let (bid, ask) = rp
+                .get_reference(self.cfg.pricing, self.cfg.pricing_volume)
+                .unwrap_or(rp.get());
+            let (bid_volume, ask_volume) = rp.get_volumes();
+            quotes.push(Quote {
+                name: v.name.clone(),
+                weight: v.weight,
+                bid,
+                bid_volume,
+                ask,
+                ask_volume,
+                event_time: rp.get_event_time(),
+            });
+        }
+        return quotes;
+    }
+
+    fn reject_outliers(&mut self, quotes: Vec<Quote>) -> Vec<Quote> {
+        if quotes.is_empty() {
+            return quotes;
+        }
+
+        let median_mid = median(quotes.iter().map(|q| q.mid()).collect());
+        let mut valid = vec![];
+        for q in quotes.into_iter() {
+            let deviation = (q.mid() - median_mid).abs() / median_mid;
+            if deviation > self.cfg.max_deviation {
+                if self.rejected.insert(q.name.clone()) {
+                    warn!(
+                        "rejecting {} reference, mid({}) deviates by {} from median({})",
+                        q.name,
+                        q.mid(),
+                        deviation,
+                        median_mid
+                    );
+                }
+                continue;
+            }
+            if self.rejected.remove(&q.name) {
+                info!("{} reference accepted again", q.name);
+            }
+            valid.push(q);
+        }
+        return valid;
+    }
+
+    // apply the cross rate legs to a reference, None if any leg is unavailable
+    fn cross(&self, mut bid: f64, mut ask: f64, mut event_time: u64) -> Option<(f64, f64, u64)> {
+        for leg in self.legs.iter() {
+            let rp = leg.rp.lock().unwrap();
+            if rp.get_state() != FeedState::Connected || rp.is_stale(self.cfg.max_price_age) {
+                return None;
+            }
+            let (leg_bid, leg_ask) = rp.get_cross_rate(leg.invert);
+            bid *= leg_bid;
+            ask *= leg_ask;
+            event_time = event_time.min(rp.get_event_time());
+        }
+        return Some((bid, ask, event_time));
+    }
+
+    fn unavailable(&self, out: &mut RefPrice, reason: String) {
+        if out.get_state() == FeedState::Connected {
+            warn!("reference unavailable: {}", reason);
+        }
+        out.set_state(FeedState::Disconnected);
+    }
+
+    fn aggregate(&mut self) {
+        let quotes = self.quotes();
+        let quotes = self.reject_outliers(quotes);
+
+        let out = self.out.clone();
+        let mut out = out.lock().unwrap();
+        if quotes.len() < self.cfg.min_venues || quotes.is_empty() {
+            let reason = format!(
+                "only {} valid venues, {} required",
+                quotes.len(),
+                self.cfg.min_venues
+            );
+            self.unavailable(&mut out, reason);
+            return;
+        }
+
+        let (bid, ask) = match self.cfg.method {
+            Aggregation::Median => (
+                median(quotes.iter().map(|q| q.bid).collect()),
+                median(quotes.iter().map(|q| q.ask).collect()),
+            ),
+            Aggregation::Weighted => (weighted(&quotes, |q| q.bid), weighted(&quotes, |q| q.ask)),
+        };
+        // the reference is as old as the oldest price used
+        let event_time = quotes.iter().map(|q| q.event_time).min().unwrap();
+        let (bid, ask, event_time) = match self.cross(bid, ask, event_time) {
+            Some(r) => r,
+            None => {
+                self.unavailable(&mut out, "cross rate leg unavailable".to_string());
+                return;
+            }
+        };
+        if out.get() != (bid, ask) {
+            let _ = self.events.send(Event::ReferencePrice { bid, ask });
+        }
+        out.set(
+            bid,
+            quotes.iter().map(|q| q.bid_volume).sum(),
+            ask,
+            quotes.iter().map(|q| q.ask_volume).sum(),
+            Some(event_time),
+        );
+        out.set_state(FeedState::Connected);
+    }
+}
+
+pub async fn start(
+    venues: Vec<Venue>,
+    legs: Vec<CrossLeg>,
+    cfg: AggregatorConfig,
+    out: Arc<Mutex<RefPrice>>,
+    events: events::Sender,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!(
+        "aggregating references from: {:?}",
+        venues.iter().map(|v| v.name.clone()).collect::<Vec<_>>()
+    );
+    for leg in legs.iter() {
+        info!(
+            "converting reference through {} (inverted: {})",
+            leg.name, leg.invert
+        );
+    }
+    let mut aggregator = Aggregator {
+        venues,
+        legs,
+        cfg,
+        out,
+        events,
+        rejected: HashSet::new(),
+    };
+
+    let mut interval = time::interval(AGGREGATION_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => aggregator.aggregate(),
+            _ = shutdown.changed() => {
+                info!("reference aggregator stopped");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn now_ms() -> u64 {
+        return SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+    }
+
+    fn rp(bid: f64, ask: f64, event_time: u64) -> Arc<Mutex<RefPrice>> {
+        let mut rp = RefPrice::new();
+        rp.set(bid, 1., ask, 1., Some(event_time));
+        rp.set_state(FeedState::Connected);
+        return Arc::new(Mutex::new(rp));
+    }
+
+    fn venue(name: &str, weight: f64, bid: f64, ask: f64) -> Venue {
+        return Venue {
+            name: name.to_string(),
+            weight,
+            rp: rp(bid, ask, now_ms()),
+        };
+    }
+
+    fn aggregator(venues: Vec<Venue>, method: Aggregation, min_venues: usize) -> Aggregator {
+        return Aggregator {
+            venues,
+            legs: vec![],
+            cfg: AggregatorConfig {
+                method,
+                max_deviation: 0.01,
+                min_venues,
+                max_price_age: Duration::from_secs(5),
+                pricing: RefPricing::Top,
+                pricing_volume: 0.,
+            },
+            out: Arc::new(Mutex::new(RefPrice::new())),
+            events: events::channel(),
+            rejected: HashSet::new(),
+        };
+    }
+
+    fn reference(a: &Aggregator) -> Option<(f64, f64)> {
+        let out = a.out.lock().unwrap();
+        if out.get_state() != FeedState::Connected {
+            return None;
+        }
+        return Some(out.get());
+    }
+
+    fn assert_close(got: (f64, f64), want: (f64, f64)) {
+        assert!(
+            (got.0 - want.0).abs() < 1e-9 && (got.1 - want.1).abs() < 1e-9,
+            "got {:?}, want {:?}",
+            got,
+            want
+        );
+    }
+
+    #[test]
+    fn median_of_the_venues() {
+        let mut a = aggregator(
+            vec![
+                venue("a", 1., 100., 101.),
+                venue("b", 1., 100.4, 101.2),
+                venue("c", 1., 100.2, 100.9),
+            ],
+            Aggregation::Median,
+            2,
+        );
+        a.aggregate();
+        assert_close(reference(&a).unwrap(), (100.2, 101.));
+    }
+
+    #[test]
+    fn weighted_average_of_the_venues() {
+        let mut a = aggregator(
+            vec![venue("a", 1., 100., 101.), venue("b", 3., 100.4, 101.4)],
+            Aggregation::Weighted,
+            2,
+        );
+        a.aggregate();
+        assert_close(reference(&a).unwrap(), (100.3, 101.3));
+    }
+
+    #[test]
+    fn one_glitching_venue_of_three_is_rejected() {
+        let mut a = aggregator(
+            vec![
+                venue("a", 1., 100., 101.),
+                venue("b", 1., 100.2, 101.2),
+                venue("glitch", 1., 150., 151.),
+            ],
+            Aggregation::Weighted,
+            2,
+        );
+        a.aggregate();
+        assert_close(reference(&a).unwrap(), (100.1, 101.1));
+        assert!(a.rejected.contains("glitch"));
+    }
+
+    #[test]
+    fn two_disagreeing_venues_are_both_rejected() {
+        let mut a = aggregator(
+            vec![venue("a", 1., 100., 101.), venue("b", 1., 110., 111.)],
+            Aggregation::Median,
+            1,
+        );
+        a.aggregate();
+        assert_eq!(reference(&a), None);
+        assert_eq!(a.rejected.len(), 2);
+    }
+
+    #[test]
+    fn stale_and_disconnected_venues_are_skipped() {
+        let stale = Venue {
+            name: "stale".to_string(),
+            weight: 1.,
+            rp: rp(90., 91., now_ms() - 60_000),
+        };
+        let disconnected = venue("disconnected", 1., 80., 81.);
+        disconnected
+            .rp
+            .lock()
+            .unwrap()
+            .set_state(FeedState::Disconnected);
+
+        let venues = vec![venue("a", 1., 100., 101.), stale, disconnected];
+        let mut a = aggregator(venues, Aggregation::Median, 2);
+        a.aggregate();
+        assert_eq!(reference(&a), None);
+
+        a.cfg.min_venues = 1;
+        a.aggregate();
+        assert_close(reference(&a).unwrap(), (100., 101.));
+    }
+
+    fn leg(name: &str, invert: bool, bid: f64, ask: f64, event_time: u64) -> CrossLeg {
+        return CrossLeg {
+            name: name.to_string(),
+            invert,
+            rp: rp(bid, ask, event_time),
+        };
+    }
+
+    #[test]
+    fn reference_is_crossed_through_direct_and_inverted_legs() {
+        let now = now_ms();
+        let mut a = aggregator(vec![venue("a", 1., 100., 101.)], Aggregation::Median, 1);
+        a.legs = vec![
+            leg("direct", false, 0.9, 0.91, now - 1000),
+            // bought at the inverse of its ask, sold at the inverse of its bid
+            leg("inverted", true, 2., 4., now - 2000),
+        ];
+        a.aggregate();
+        assert_close(reference(&a).unwrap(), (100. * 0.9 / 4., 101. * 0.91 / 2.));
+        // as old as the oldest leg
+        assert_eq!(a.out.lock().unwrap().get_event_time(), now - 2000);
+    }
+
+    #[test]
+    fn stale_or_disconnected_leg_makes_the_reference_unavailable() {
+        let now = now_ms();
+        let mut a = aggregator(vec![venue("a", 1., 100., 101.)], Aggregation::Median, 1);
+        a.legs = vec![leg("stale", false, 0.9, 0.91, now - 60_000)];
+        a.aggregate();
+        assert_eq!(reference(&a), None);
+
+        a.legs = vec![leg("fresh", false, 0.9, 0.91, now)];
+        a.aggregate();
+        assert!(reference(&a).is_some());
+
+        a.legs[0]
+            .rp
+            .lock()
+            .unwrap()
+            .set_state(FeedState::Disconnected);
+        a.aggregate();
+        assert_eq!(reference(&a), None);
+    }
+}
@@ -1,56 +1,308 @@
+use hyper::header::{
+    AUTHORIZATION, CONNECTION, CONTENT_TYPE, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE,
+};
 use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server};
-use log::error;
-use serde::{Deserialize, Serialize};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, warn};
+use serde::Serialize;
 use std::convert::Infallible;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio_tungstenite::tungstenite::{handshake::derive_accept_key, protocol::Role};
+use tokio_tungstenite::WebSocketStream;
 
-use crate::{binance_ws::RefPrice, vega_store::VegaStore};
-
-#[derive(Serialize, Deserialize)]
-struct Resp {
-    best_bid: f64,
-    best_ask: f64,
-    position: String,
-    market: String,
-    market_data: String,
-    accounts: String,
-    orders: String,
-    assets: String,
-}
-
-async fn handle(
-    store: Arc<Mutex<VegaStore>>,
-    rp: Arc<Mutex<RefPrice>>,
-    _req: Request<Body>,
-) -> Result<Response<Body>, Infallible> {
-    let (bb, ba) = rp.lock().unwrap().get();
-    // lazy implementation, none of these implement Serde interface, so just dumping strings
-    Ok(Response::new(Body::from(
-        serde_json::to_string(&Resp {
-            best_bid: bb,
-            best_ask: ba,
-            position: format!("{:?}", store.lock().unwrap().get_position()),
-            accounts: format!("{:?}", store.lock().unwrap().get_accounts()),
-            orders: format!("{:?}", store.lock().unwrap().get_orders()),
-            market: format!("{:?}", store.lock().unwrap().get_market()),
-            market_data: format!("{:?}", store.lock().unwrap().get_market_data()),
-            assets: format!("{:?}", store.lock().unwrap().get_assets()),
-        })
-        .unwrap(),
-    )))
-}
-
-pub async fn start(port: u16, store: Arc<Mutex<VegaStore>>, rp: Arc<Mutex<RefPrice>>) {
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+use crate::{
+    config::Secret,
+    control::{Control, ParamsUpdate},
+    events::{self, Event},
+    health::{Check, Health},
+    metrics::Metrics,
+    pnl::{Pnl, PnlReport},
+    push,
+    ref_price::RefPrice,
+    vega_store::VegaStore,
+    views::{
+        AccountView, AssetView, MarketDataView, MarketView, OrderView, PositionView, RefPriceView,
+        StreamView, TradeView,
+    },
+};
 
-    let make_service = make_service_fn(move |_conn: &AddrStream| {
-        let store = store.clone();
-        let rp = rp.clone();
+// overview of the bot served on /
+#[derive(Serialize)]
+struct Status {
+    reference_price: RefPriceView,
+    vega_streams: Vec<StreamView>,
+    pnl: Option<PnlReport>,
+    live_orders: usize,
+}
+
+/// Everything the API reads from and acts on
+#[derive(Clone)]
+pub struct Context {
+    pub store: Arc<Mutex<VegaStore>>,
+    pub rp: Arc<Mutex<RefPrice>>,
+    pub pnl: Arc<Mutex<Pnl>>,
+    pub control: Arc<Mutex<Control>>,
+    pub metrics: Arc<Mutex<Metrics>>,
+    pub events: events::Sender,
+    pub push: push::Sender,
+    pub health: Arc<Mutex<Health>>,
+    pub max_price_age: Duration,
+    // the control endpoints are disabled without a token
+    pub token: Option<Secret>,
+}
+
+async fn handle(ctx: Context, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    if path == "/control" || path.starts_with("/control/") {
+        return Ok(handle_control(ctx, req).await);
+    }
+    if path == "/ws" {
+        return Ok(upgrade(ctx, req));
+    }
+    if req.method() != Method::GET {
+        return Ok(error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"));
+    }
+
+    let Context {
+        store,
+        rp,
+        pnl,
+        metrics,
+        health,
+        max_price_age,
+        ..
+    } = ctx;
+    let resp = match path.as_str() {
+        "/" => {
+            // the pnl is always locked before the store
+            let pnl = pnl.lock().unwrap().report(&store.lock().unwrap()).ok();
+            let store = store.lock().unwrap();
+            json(&Status {
+                reference_price: RefPriceView::from(&*rp.lock().unwrap()),
+                vega_streams: views(&store.get_streams(), StreamView::from),
+                pnl,
+                live_orders: store.get_orders().len(),
+            })
+        }
+        "/reference-price" => json(&RefPriceView::from(&*rp.lock().unwrap())),
+        "/position" => json(
+            &store
+                .lock()
+                .unwrap()
+                .get_position()
+                .as_ref()
+                .map(PositionView::from),
+        ),
+        "/orders" => json(&views(&store.lock().unwrap().get_orders(), OrderView::from)),
+        "/fills" => json(&views(&store.lock().unwrap().get_fills(), TradeView::from)),
+        "/accounts" => json(&views(
+            &store.lock().unwrap().get_accounts(),
+            AccountView::from,
+        )),
+        "/market" => json(&MarketView::from(&store.lock().unwrap().get_market())),
+        "/market-data" => json(&MarketDataView::from(
+            &store.lock().unwrap().get_market_data(),
+        )),
+        "/assets" => json(&views(&store.lock().unwrap().get_assets(), AssetView::from)),
+        "/streams" => json(&views(
+            &store.lock().unwrap().get_streams(),
+            StreamView::from,
+        )),
+        "/pnl" => match pnl.lock().unwrap().report(&store.lock().unwrap()) {
+            Ok(report) => json(&report),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        "/metrics" => {
+            let text = metrics
+                .lock()
+                .unwrap()
+                .render(&store.lock().unwrap(), &rp.lock().unwrap());
+            match text {
+                Ok(text) => Response::builder()
+                    .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Body::from(text))
+                    .unwrap(),
+                Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            }
+        }
+        "/healthz" => probe(health.lock().unwrap().liveness()),
+        "/readyz" => {
+            let checks = health.lock().unwrap().readiness(
+                &store.lock().unwrap(),
+                &rp.lock().unwrap(),
+                max_price_age,
+            );
+            probe(checks)
+        }
+        _ => error(StatusCode::NOT_FOUND, "not found"),
+    };
+    return Ok(resp);
+}
+
+// switches the connection to a websocket pushing the bot state changes
+fn upgrade(ctx: Context, req: Request<Body>) -> Response<Body> {
+    let key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) if req.method() == Method::GET => key,
+        _ => return error(StatusCode::BAD_REQUEST, "expected a websocket upgrade"),
+    };
+    let accept = derive_accept_key(key.as_bytes());
+
+    // subscribe now so nothing is missed during the handshake
+    let messages = ctx.push.subscribe();
+    let events = ctx.events.subscribe();
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                push::serve(ws, messages, events, ctx.rp).await;
+            }
+            Err(e) => warn!("websocket upgrade failed: {}", e),
+        }
+    });
+
+    return Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap();
+}
+
+// control endpoints, the changes are picked up by the strategy on its next run
+async fn handle_control(ctx: Context, req: Request<Body>) -> Response<Body> {
+    if let Err(resp) = authorize(&ctx, &req) {
+        return resp;
+    }
+
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    match (method, path.as_str()) {
+        (Method::GET, "/control") => {
+            return json(&ctx.control.lock().unwrap().view());
+        }
+        (Method::POST, "/control/pause") => {
+            warn!("quoting paused from the API");
+            ctx.control.lock().unwrap().set_paused(true);
+        }
+        (Method::POST, "/control/resume") => {
+            warn!("quoting resumed from the API");
+            ctx.control.lock().unwrap().set_paused(false);
+        }
+        (Method::POST, "/control/cancel-all") => {
+            warn!("cancel all requested from the API");
+            ctx.control.lock().unwrap().request_cancel_all();
+        }
+        (Method::POST, "/control/params") => {
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            let update = match serde_json::from_slice::<ParamsUpdate>(&body) {
+                Ok(update) => update,
+                Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            if let Err(errors) = ctx.control.lock().unwrap().update(&update) {
+                return error(StatusCode::BAD_REQUEST, &errors.join(", "));
+            }
+            warn!("strategy parameters updated from the API: {:?}", update);
+        }
+        (_, "/control")
+        | (_, "/control/pause")
+        | (_, "/control/resume")
+        | (_, "/control/cancel-all")
+        | (_, "/control/params") => {
+            return error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        }
+        _ => return error(StatusCode::NOT_FOUND, "not found"),
+    }
 
-        let service = service_fn(move |req| handle(store.clone(), rp.clone(), req));
+    // wake the strategy so the change is applied right away
+    let _ = ctx.events.send(Event::Control);
+    return json(&ctx.control.lock().unwrap().view());
+}
+
+// the token is expected as a bearer token in the authorization header
+fn authorize(ctx: &Context, req: &Request<Body>) -> Result<(), Response<Body>> {
+    let token = match &ctx.token {
+        Some(token) => token.expose(),
+        None => {
+            return Err(error(
+                StatusCode::FORBIDDEN,
+                "control endpoints are disabled without an api token",
+            ))
+        }
+    };
+    let given = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    return match given {
+        // compared in constant time so the token cannot be guessed from the timing
+        Some(given) if bool::from(given.as_bytes().ct_eq(token.as_bytes())) => Ok(()),
+        _ => Err(error(
+            StatusCode::UNAUTHORIZED,
+            "invalid or missing api token",
+        )),
+    };
+}
+
+#[derive(Serialize)]
+struct ProbeResp {
+    ok: bool,
+    checks: Vec<Check>,
+}
+
+// 503 as soon as one of the checks fails
+fn probe(checks: Vec<Check>) -> Response<Body> {
+    let ok = checks.iter().all(|c| c.ok);
+    let mut resp = json(&ProbeResp { ok, checks });
+    if !ok {
+        *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    return resp;
+}
+
+fn views<'a, T, V>(items: &'a [T], view: impl Fn(&'a T) -> V) -> Vec<V> {
+    return items.iter().map(view).collect();
+}
+
+fn json<T: Serialize>(body: &T) -> Response<Body> {
+    return match serde_json::to_string(body) {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+}
+
+#[derive(Serialize)]
+struct ErrorResp<'a> {
+    error: &'a str,
+}
+
+fn error(status: StatusCode, msg: &str) -> Response<Body> {
+    return Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_string(&ErrorResp { error: msg }).unwrap(),
+        ))
+        .unwrap();
+}
+
+pub async fn start(host: IpAddr, port: u16, ctx: Context) {
+    let addr = SocketAddr::new(host, port);
+
+    let make_service = make_service_fn(move |_conn: &AddrStream| {
+        let ctx = ctx.clone();
+        let service = service_fn(move |req| handle(ctx.clone(), req));
 
         async move { Ok::<_, Infallible>(service) }
     });
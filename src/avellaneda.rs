@@ -0,0 +1,171 @@
+use log::info;
+use num_traits::ToPrimitive;
+use rust_decimal::Decimal;
+use std::time::Instant;
+use vega_wallet_client::commands::Side;
+
+use crate::{
+    decimals, ladder,
+    strategy::{Error, Quote, Snapshot, Strategy},
+    strategy_config::{AvellanedaConfig, StrategyConfig},
+};
+
+// basis points in one
+const BPS: f64 = 10000.;
+
+/// Quotes the first level at the Avellaneda-Stoikov bid and ask,
+/// the deeper levels and the sizes follow the ladder settings
+pub struct AvellanedaStrategy {
+    cfg: StrategyConfig,
+    vol: Volatility,
+}
+
+impl AvellanedaStrategy {
+    pub fn new(cfg: StrategyConfig) -> AvellanedaStrategy {
+        let vol = Volatility::new(&cfg.avellaneda);
+        return AvellanedaStrategy { cfg, vol };
+    }
+}
+
+impl Strategy for AvellanedaStrategy {
+    fn name(&self) -> &'static str {
+        return "avellaneda-stoikov";
+    }
+
+    fn set_config(&mut self, cfg: StrategyConfig) {
+        self.cfg = cfg;
+    }
+
+    fn quotes(&mut self, snap: &Snapshot) -> Result<Vec<Quote>, Error> {
+        self.vol.update(snap.mid(), Instant::now());
+
+        let (bid_volume, offer_volume) = ladder::side_volumes(&self.cfg, snap)?;
+        let (open_volume, _) = snap.position()?;
+
+        let volatility = self.vol.get();
+        let q = quotes(
+            &self.cfg.avellaneda,
+            snap.mid(),
+            volatility,
+            open_volume.to_f64().unwrap_or(0.),
+        );
+        info!(
+            "avellaneda-stoikov quotes: {:?}, volatility({})",
+            q, volatility
+        );
+
+        let mut desired = ladder::levels(
+            &self.cfg,
+            Side::Buy,
+            decimals::from_f64(q.bid)?,
+            Decimal::ZERO,
+            bid_volume,
+        )?;
+        desired.append(&mut ladder::levels(
+            &self.cfg,
+            Side::Sell,
+            decimals::from_f64(q.ask)?,
+            Decimal::ZERO,
+            offer_volume,
+        )?);
+        return Ok(desired);
+    }
+}
+
+/// Exponentially weighted estimate of the reference price volatility,
+/// as a relative standard deviation per square root of second
+pub struct Volatility {
+    half_life_secs: f64,
+    variance: f64,
+    last: Option<(f64, Instant)>,
+}
+
+impl Volatility {
+    pub fn new(cfg: &AvellanedaConfig) -> Volatility {
+        return Volatility {
+            half_life_secs: cfg.volatility_half_life_secs,
+            variance: cfg.initial_volatility * cfg.initial_volatility,
+            last: None,
+        };
+    }
+
+    pub fn update(&mut self, mid: f64, now: Instant) {
+        if mid <= 0. {
+            return;
+        }
+
+        if let Some((last_mid, last_at)) = self.last {
+            let dt = now.duration_since(last_at).as_secs_f64();
+            if dt <= 0. {
+                return;
+            }
+            let ret = (mid / last_mid).ln();
+            // the weight of a sample grows with the time it covers
+            let alpha = 1. - (-dt * std::f64::consts::LN_2 / self.half_life_secs).exp();
+            self.variance += alpha * (ret * ret / dt - self.variance);
+        }
+        self.last = Some((mid, now));
+    }
+
+    pub fn get(&self) -> f64 {
+        return self.variance.sqrt();
+    }
+}
+
+/// Bid and ask prices of the first level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quotes {
+    pub reservation: f64,
+    pub spread: f64,
+    pub bid: f64,
+    pub ask: f64,
+}
+
+/// Computes the Avellaneda-Stoikov reservation price and optimal spread
+/// around the mid, for the given position and relative volatility
+pub fn quotes(cfg: &AvellanedaConfig, mid: f64, volatility: f64, position: f64) -> Quotes {
+    // the model works in bps of the mid, whatever the price of the market
+    let sigma = volatility * BPS;
+    let risk = cfg.gamma * sigma * sigma * cfg.horizon_secs;
+
+    let reservation = mid * (1. - position * risk / BPS);
+    let spread = mid * (risk + (2. / cfg.gamma) * (1. + cfg.gamma / cfg.kappa).ln()) / BPS;
+
+    return Quotes {
+        reservation,
+        spread,
+        bid: reservation - spread / 2.,
+        ask: reservation + spread / 2.,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_is_relative_to_the_mid() {
+        let cfg = AvellanedaConfig::default();
+        let low = quotes(&cfg, 0.5, 0.0001, 0.);
+        let high = quotes(&cfg, 50000., 0.0001, 0.);
+
+        assert!((low.spread / 0.5 - high.spread / 50000.).abs() < 1e-12);
+        assert!(low.bid > 0.);
+        // gamma 0.1 and kappa 0.1 give about 20bps
+        let bps = high.spread / 50000. * BPS;
+        assert!(bps > 19. && bps < 21., "spread of {}bps", bps);
+    }
+
+    #[test]
+    fn reservation_moves_against_the_position() {
+        let cfg = AvellanedaConfig::default();
+        let flat = quotes(&cfg, 100., 0.0001, 0.);
+        let long = quotes(&cfg, 100., 0.0001, 1.);
+        let short = quotes(&cfg, 100., 0.0001, -1.);
+
+        assert_eq!(flat.reservation, 100.);
+        assert!(long.reservation < flat.reservation);
+        assert!(short.reservation > flat.reservation);
+        assert_eq!(long.spread, flat.spread);
+    }
+}
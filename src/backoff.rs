@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+// delays between the reconnection attempts of the feeds and the streams
+const RECONNECT_INITIAL: Duration = Duration::from_millis(500);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        return Backoff {
+            initial,
+            max,
+            current: initial,
+        };
+    }
+
+    pub fn reconnect() -> Backoff {
+        return Backoff::new(RECONNECT_INITIAL, RECONNECT_MAX);
+    }
+
+    // return the delay to wait before the next attempt,
+    // and double the one used for the attempt after that
+    pub fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = std::cmp::min(self.current * 2, self.max);
+        return delay;
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
@@ -1,33 +1,16 @@
+use futures::future::BoxFuture;
+use hyper_tls::HttpsConnector;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::error::Error as StdError;
-use std::fmt;
-use std::sync::{Arc, Mutex};
-use tungstenite::{connect, Message};
-use url::Url;
-
-pub struct RefPrice {
-    bid_price: f64,
-    ask_price: f64,
-}
-
-impl RefPrice {
-    pub fn new() -> RefPrice {
-        return RefPrice {
-            bid_price: 0.,
-            ask_price: 0.,
-        };
-    }
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time;
 
-    pub fn set(&mut self, bid_price: f64, ask_price: f64) {
-        self.bid_price = bid_price;
-        self.ask_price = ask_price;
-    }
+use crate::price_source::{parse_levels, parse_price, parse_volume, Error, Handled, PriceSource};
+use crate::ref_price::RefPrice;
 
-    pub fn get(&self) -> (f64, f64) {
-        return (self.bid_price, self.ask_price);
-    }
-}
+const REST_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Request {
@@ -37,80 +20,272 @@ struct Request {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Response {
+struct SubscribeResponse {
+    result: Option<serde_json::Value>,
+    id: u64,
+}
+
+/// The binance stream used to build the reference price
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RefSource {
+    /// 24h rolling ticker, pushed every second
+    Ticker,
+    /// Real-time best bid and ask
+    BookTicker,
+    /// Volume weighted mid of the top levels of the book
+    Depth,
+    /// Full local order book, synced from a REST snapshot
+    DiffDepth,
+}
+
+impl RefSource {
+    fn stream(&self, mkt: &str, depth_levels: u32) -> String {
+        use RefSource::*;
+        let stream = match self {
+            Ticker => "ticker".to_string(),
+            BookTicker => "bookTicker".to_string(),
+            Depth => format!("depth{}@100ms", depth_levels),
+            DiffDepth => "depth@100ms".to_string(),
+        };
+        return format!("{}@{}", mkt.to_lowercase(), stream);
+    }
+}
+
+/// A binance symbol used as a leg of a cross rate, in the form
+/// SYMBOL or SYMBOL:invert for pairs quoted the other way around
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CrossRate {
+    pub symbol: String,
+    pub invert: bool,
+}
+
+impl FromStr for CrossRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.split_once(':') {
+            None => Ok(CrossRate {
+                symbol: s.to_string(),
+                invert: false,
+            }),
+            Some((symbol, "invert")) => Ok(CrossRate {
+                symbol: symbol.to_string(),
+                invert: true,
+            }),
+            Some((_, other)) => Err(format!("invalid cross rate modifier: {}", other)),
+        };
+    }
+}
+
+impl TryFrom<String> for CrossRate {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        return s.parse();
+    }
+}
+
+pub struct FeedConfig {
+    pub ws_url: String,
+    pub rest_url: String,
+    pub market: String,
+    pub source: RefSource,
+    pub depth_levels: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TickerResponse {
     pub e: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    pub a: String,
+    #[serde(rename = "A")]
+    pub a_qty: String,
+    pub b: String,
+    #[serde(rename = "B")]
+    pub b_qty: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BookTickerResponse {
+    pub u: u64,
     pub a: String,
+    #[serde(rename = "A")]
+    pub a_qty: String,
     pub b: String,
+    #[serde(rename = "B")]
+    pub b_qty: String,
+}
+
+// used both for the partial depth stream and the REST snapshot
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DepthResponse {
+    pub last_update_id: u64,
+    pub bids: Vec<(String, String)>,
+    pub asks: Vec<(String, String)>,
 }
 
-pub async fn start(ws_url: String, mkt: String, rp: Arc<Mutex<RefPrice>>) -> Result<(), Error> {
-    let url = ws_url.parse::<Url>()?;
-    info!("opening websocket with binance API at: {}", url);
-    let (mut socket, _) = connect(url)?;
-    info!("connected to binance successfully");
+#[derive(Debug, Serialize, Deserialize)]
+struct DepthUpdateResponse {
+    pub e: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    pub b: Vec<(String, String)>,
+    pub a: Vec<(String, String)>,
+}
 
-    let request = serde_json::to_string(&Request {
-        id: 1,
-        method: "SUBSCRIBE".to_string(),
-        params: vec![format!("{}@ticker", mkt.to_lowercase())],
-    })?;
+// returns the volume weighted price and the total volume of the levels
+fn vwap(levels: &[(f64, f64)]) -> Result<(f64, f64), Error> {
+    let (mut notional, mut volume) = (0., 0.);
+    for (p, q) in levels.iter() {
+        notional += p * q;
+        volume += q;
+    }
+    if volume == 0. {
+        return Err(Error::EmptyBook);
+    }
+    return Ok((notional / volume, volume));
+}
 
-    socket.write_message(Message::Text(request))?;
+async fn fetch_snapshot(rest_url: &str, mkt: &str) -> Result<DepthResponse, Error> {
+    let uri = format!(
+        "{}/api/v3/depth?symbol={}&limit=1000",
+        rest_url,
+        mkt.to_uppercase()
+    )
+    .parse::<hyper::Uri>()?;
+    info!("fetching binance order book snapshot at: {}", uri);
 
-    // discard first message, it's confirmation from binance
-    socket.read_message()?;
-    loop {
-        let msg = socket.read_message()?;
-        match serde_json::from_str::<Response>(&msg.to_string()) {
-            Ok(r) => {
-                if r.e == "24hrTicker" {
-                    info!("new binance prices: {:?}", r);
-                    rp.lock()
-                        .unwrap()
-                        .set(r.b.parse::<f64>().unwrap(), r.a.parse::<f64>().unwrap());
-                }
-            }
-            _ => continue,
-        }
+    let clt = hyper::Client::builder().build::<_, hyper::Body>(HttpsConnector::new());
+    let resp = time::timeout(REST_TIMEOUT, clt.get(uri)).await??;
+    if !resp.status().is_success() {
+        return Err(Error::RestError);
     }
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+    return Ok(serde_json::from_slice::<DepthResponse>(&body)?);
 }
 
-#[derive(Debug)]
-pub enum Error {
-    WSError,
+pub struct BinanceSource {
+    cfg: FeedConfig,
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "wallet client error: {}", self.desc())
+impl BinanceSource {
+    pub fn new(cfg: FeedConfig) -> BinanceSource {
+        return BinanceSource { cfg };
     }
 }
 
-impl From<tungstenite::Error> for Error {
-    fn from(_: tungstenite::Error) -> Self {
-        Error::WSError
+impl PriceSource for BinanceSource {
+    fn name(&self) -> String {
+        return format!("binance({})", self.cfg.market);
     }
-}
 
-impl From<url::ParseError> for Error {
-    fn from(_: url::ParseError) -> Self {
-        Error::WSError
+    fn url(&self) -> String {
+        return self.cfg.ws_url.clone();
     }
-}
 
-impl From<serde_json::Error> for Error {
-    fn from(_: serde_json::Error) -> Self {
-        Error::WSError
+    fn subscriptions(&self) -> Result<Vec<String>, Error> {
+        return Ok(vec![serde_json::to_string(&Request {
+            id: 1,
+            method: "SUBSCRIBE".to_string(),
+            params: vec![self
+                .cfg
+                .source
+                .stream(&self.cfg.market, self.cfg.depth_levels)],
+        })?]);
     }
-}
 
-impl StdError for Error {}
+    fn handle_message(&mut self, text: &str, rp: &Mutex<RefPrice>) -> Result<Handled, Error> {
+        if serde_json::from_str::<SubscribeResponse>(text).is_ok() {
+            return Ok(Handled::Subscribed);
+        }
 
-impl Error {
-    pub fn desc(&self) -> String {
-        use Error::*;
-        match self {
-            WSError => format!("websocket error"),
+        match self.cfg.source {
+            RefSource::Ticker => {
+                let r = serde_json::from_str::<TickerResponse>(text)?;
+                if r.e == "24hrTicker" {
+                    info!("new binance prices: {:?}", r);
+                    rp.lock().unwrap().set(
+                        parse_price(&r.b)?,
+                        parse_volume(&r.b_qty)?,
+                        parse_price(&r.a)?,
+                        parse_volume(&r.a_qty)?,
+                        Some(r.event_time),
+                    );
+                }
+            }
+            RefSource::BookTicker => {
+                let r = serde_json::from_str::<BookTickerResponse>(text)?;
+                info!("new binance prices: {:?}", r);
+                rp.lock().unwrap().set(
+                    parse_price(&r.b)?,
+                    parse_volume(&r.b_qty)?,
+                    parse_price(&r.a)?,
+                    parse_volume(&r.a_qty)?,
+                    None,
+                );
+            }
+            RefSource::Depth => {
+                let r = serde_json::from_str::<DepthResponse>(text)?;
+                let (bids, asks) = (parse_levels(&r.bids)?, parse_levels(&r.asks)?);
+                let (bid, bid_volume) = vwap(&bids)?;
+                let (ask, ask_volume) = vwap(&asks)?;
+                let mid = (bid + ask) / 2.;
+                info!("new binance mid of depth: {}", mid);
+                let mut rp = rp.lock().unwrap();
+                rp.get_book_mut()
+                    .apply_snapshot(r.last_update_id, &bids, &asks);
+                rp.set(mid, bid_volume, mid, ask_volume, None);
+            }
+            RefSource::DiffDepth => {
+                let r = serde_json::from_str::<DepthUpdateResponse>(text)?;
+                if r.e != "depthUpdate" {
+                    return Ok(Handled::Ignored);
+                }
+                let (bids, asks) = (parse_levels(&r.b)?, parse_levels(&r.a)?);
+                let mut rp = rp.lock().unwrap();
+                if !rp
+                    .get_book_mut()
+                    .apply_diff(r.first_update_id, r.final_update_id, &bids, &asks)
+                {
+                    return Err(Error::OutOfSync);
+                }
+                let ((bid, bid_volume), (ask, ask_volume)) =
+                    match (rp.get_book().best_bid(), rp.get_book().best_ask()) {
+                        (Some(b), Some(a)) => (b, a),
+                        _ => return Err(Error::EmptyBook),
+                    };
+                rp.set(bid, bid_volume, ask, ask_volume, Some(r.event_time));
+            }
         }
+
+        return Ok(Handled::Price);
+    }
+
+    // diff events are buffered by the socket while we fetch
+    // the snapshot, older ones are dropped by the book
+    fn on_subscribed<'a>(
+        &'a mut self,
+        rp: &'a Mutex<RefPrice>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        return Box::pin(async move {
+            if self.cfg.source != RefSource::DiffDepth {
+                return Ok(());
+            }
+            let snap = fetch_snapshot(&self.cfg.rest_url, &self.cfg.market).await?;
+            let (bids, asks) = (parse_levels(&snap.bids)?, parse_levels(&snap.asks)?);
+            rp.lock()
+                .unwrap()
+                .get_book_mut()
+                .apply_snapshot(snap.last_update_id, &bids, &asks);
+            return Ok(());
+        });
     }
 }
@@ -0,0 +1,101 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use crate::price_source::{parse_levels, Error, Handled, PriceSource};
+use crate::ref_price::RefPrice;
+
+pub struct BybitSource {
+    ws_url: String,
+    symbol: String,
+}
+
+impl BybitSource {
+    pub fn new(ws_url: String, symbol: String) -> BybitSource {
+        return BybitSource { ws_url, symbol };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    op: String,
+    args: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpResponse {
+    op: String,
+    success: Option<bool>,
+    ret_msg: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BookData {
+    b: Vec<(String, String)>,
+    a: Vec<(String, String)>,
+    u: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BookResponse {
+    topic: String,
+    r#type: String,
+    ts: u64,
+    data: BookData,
+}
+
+impl PriceSource for BybitSource {
+    fn name(&self) -> String {
+        return format!("bybit({})", self.symbol);
+    }
+
+    fn url(&self) -> String {
+        return self.ws_url.clone();
+    }
+
+    fn subscriptions(&self) -> Result<Vec<String>, Error> {
+        return Ok(vec![serde_json::to_string(&Request {
+            op: "subscribe".to_string(),
+            args: vec![format!("orderbook.1.{}", self.symbol.to_uppercase())],
+        })?]);
+    }
+
+    fn handle_message(&mut self, text: &str, rp: &Mutex<RefPrice>) -> Result<Handled, Error> {
+        if let Ok(r) = serde_json::from_str::<OpResponse>(text) {
+            return match (r.op.as_str(), r.success) {
+                ("subscribe", Some(true)) => Ok(Handled::Subscribed),
+                ("subscribe", _) => {
+                    info!("bybit subscription error: {:?}", r.ret_msg);
+                    Err(Error::InvalidMessage)
+                }
+                // pong
+                _ => Ok(Handled::Ignored),
+            };
+        }
+
+        let r = serde_json::from_str::<BookResponse>(text)?;
+        let (bids, asks) = (parse_levels(&r.data.b)?, parse_levels(&r.data.a)?);
+        let mut rp = rp.lock().unwrap();
+        match r.r#type.as_str() {
+            "snapshot" => rp.get_book_mut().apply_snapshot(r.data.u, &bids, &asks),
+            _ => rp.get_book_mut().apply_update(&bids, &asks),
+        }
+        let ((bid, bid_volume), (ask, ask_volume)) =
+            match (rp.get_book().best_bid(), rp.get_book().best_ask()) {
+                (Some(b), Some(a)) => (b, a),
+                _ => return Err(Error::EmptyBook),
+            };
+        info!("new bybit prices: bid({}), ask({})", bid, ask);
+        rp.set(bid, bid_volume, ask, ask_volume, Some(r.ts));
+        return Ok(Handled::Price);
+    }
+
+    // bybit drops connections without a ping every 20 seconds
+    fn heartbeat(&self) -> Option<String> {
+        return serde_json::to_string(&Request {
+            op: "ping".to_string(),
+            args: vec![],
+        })
+        .ok();
+    }
+}
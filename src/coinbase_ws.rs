@@ -0,0 +1,87 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use crate::price_source::{parse_price, parse_volume, Error, Handled, PriceSource};
+use crate::ref_price::RefPrice;
+
+pub struct CoinbaseSource {
+    ws_url: String,
+    product: String,
+}
+
+impl CoinbaseSource {
+    pub fn new(ws_url: String, product: String) -> CoinbaseSource {
+        return CoinbaseSource { ws_url, product };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    r#type: String,
+    product_ids: Vec<String>,
+    channels: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    r#type: String,
+    best_bid: Option<String>,
+    best_bid_size: Option<String>,
+    best_ask: Option<String>,
+    best_ask_size: Option<String>,
+    message: Option<String>,
+}
+
+fn field(f: &Option<String>) -> Result<&str, Error> {
+    return f.as_deref().ok_or(Error::InvalidMessage);
+}
+
+impl PriceSource for CoinbaseSource {
+    fn name(&self) -> String {
+        return format!("coinbase({})", self.product);
+    }
+
+    fn url(&self) -> String {
+        return self.ws_url.clone();
+    }
+
+    fn subscriptions(&self) -> Result<Vec<String>, Error> {
+        return Ok(vec![serde_json::to_string(&Request {
+            r#type: "subscribe".to_string(),
+            product_ids: vec![self.product.clone()],
+            channels: vec!["ticker".to_string(), "heartbeat".to_string()],
+        })?]);
+    }
+
+    fn handle_message(&mut self, text: &str, rp: &Mutex<RefPrice>) -> Result<Handled, Error> {
+        let r = serde_json::from_str::<Response>(text)?;
+        match r.r#type.as_str() {
+            "subscriptions" => return Ok(Handled::Subscribed),
+            "error" => {
+                info!("coinbase error: {:?}", r.message);
+                return Err(Error::InvalidMessage);
+            }
+            // the ticker is only pushed on trades, the heartbeats sent every
+            // second show a quiet product is still streamed
+            "heartbeat" => {
+                rp.lock().unwrap().refresh();
+                return Ok(Handled::Ignored);
+            }
+            "ticker" => {}
+            _ => return Ok(Handled::Ignored),
+        }
+
+        info!("new coinbase prices: {:?}", r);
+        // the ticker time has a microsecond precision RFC3339 format,
+        // the receive time is good enough here
+        rp.lock().unwrap().set(
+            parse_price(field(&r.best_bid)?)?,
+            parse_volume(field(&r.best_bid_size)?)?,
+            parse_price(field(&r.best_ask)?)?,
+            parse_volume(field(&r.best_ask_size)?)?,
+            None,
+        );
+        return Ok(Handled::Price);
+    }
+}
@@ -0,0 +1,439 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use url::Url;
+
+use crate::aggregator::Aggregation;
+use crate::binance_ws::{CrossRate, RefSource};
+use crate::ref_price::RefPricing;
+use crate::strategy_config::{self, StrategyArgs, StrategyConfig};
+
+const WALLET_TOKEN_ENV: &str = "VEGAMM_WALLET_TOKEN";
+const API_TOKEN_ENV: &str = "VEGAMM_API_TOKEN";
+
+/// A value which must never be logged
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        return &self.0;
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+/// The bot configuration, every field can be set in the TOML file,
+/// then overridden by its VEGAMM_ environment variable, then by its flag
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: u16,
+    // address the http API listens on
+    pub api_host: String,
+    // bearer token of the control endpoints, only readable from the file
+    // or the environment, the endpoints are disabled without it
+    pub api_token: Option<Secret>,
+    pub vega_grpc_url: String,
+    pub wallet_url: String,
+    // only readable from the file, the environment or a token file
+    pub wallet_token: Option<Secret>,
+    pub wallet_token_file: Option<String>,
+    pub wallet_pubkey: String,
+    pub vega_market: String,
+    pub binance_ws_url: String,
+    pub binance_rest_url: String,
+    pub coinbase_ws_url: String,
+    pub kraken_ws_url: String,
+    pub bybit_ws_url: String,
+    pub binance_market: Option<String>,
+    pub coinbase_product: Option<String>,
+    pub kraken_pair: Option<String>,
+    pub bybit_symbol: Option<String>,
+    pub binance_weight: f64,
+    pub coinbase_weight: f64,
+    pub kraken_weight: f64,
+    pub bybit_weight: f64,
+    pub cross_rate: Vec<CrossRate>,
+    pub aggregation: Aggregation,
+    pub max_price_deviation: f64,
+    pub min_venues: usize,
+    pub binance_stream: RefSource,
+    pub binance_depth_levels: u32,
+    pub reference_pricing: RefPricing,
+    pub reference_volume: f64,
+    pub max_price_age_ms: u64,
+    // 0 never triggers the kill switch on feed loss
+    pub max_feed_down_ms: u64,
+    // how long to wait for the orders to be cancelled on shutdown
+    pub shutdown_timeout_ms: u64,
+    pub strategy: StrategyConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            port: 8080,
+            api_host: "127.0.0.1".to_string(),
+            api_token: None,
+            vega_grpc_url: "tcp://n11.testnet.vega.xyz:3007".to_string(),
+            wallet_url: "http://127.0.0.1:1789".to_string(),
+            wallet_token: None,
+            wallet_token_file: None,
+            wallet_pubkey: "".to_string(),
+            vega_market: "".to_string(),
+            binance_ws_url: "wss://stream.binance.com:443/ws".to_string(),
+            binance_rest_url: "https://api.binance.com".to_string(),
+            coinbase_ws_url: "wss://ws-feed.exchange.coinbase.com".to_string(),
+            kraken_ws_url: "wss://ws.kraken.com".to_string(),
+            bybit_ws_url: "wss://stream.bybit.com/v5/public/spot".to_string(),
+            binance_market: None,
+            coinbase_product: None,
+            kraken_pair: None,
+            bybit_symbol: None,
+            binance_weight: 1.,
+            coinbase_weight: 1.,
+            kraken_weight: 1.,
+            bybit_weight: 1.,
+            cross_rate: vec![],
+            aggregation: Aggregation::Median,
+            max_price_deviation: 0.005,
+            min_venues: 1,
+            binance_stream: RefSource::BookTicker,
+            binance_depth_levels: 20,
+            reference_pricing: RefPricing::Top,
+            reference_volume: 0.,
+            max_price_age_ms: 10000,
+            max_feed_down_ms: 60000,
+            shutdown_timeout_ms: 10000,
+            strategy: StrategyConfig::default(),
+        };
+    }
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// A TOML configuration file, overridden by the environment and the flags
+    #[arg(long, env = "VEGAMM_CONFIG")]
+    config: Option<String>,
+    /// Port of the http API
+    #[arg(long, env = "VEGAMM_PORT")]
+    port: Option<u16>,
+    /// Address the http API listens on, use 0.0.0.0 to expose the probes
+    #[arg(long, env = "VEGAMM_API_HOST")]
+    api_host: Option<String>,
+    /// A vega grpc node address
+    #[arg(long, env = "VEGAMM_VEGA_GRPC_URL")]
+    vega_grpc_url: Option<String>,
+    /// A vega wallet service address
+    #[arg(long, env = "VEGAMM_WALLET_URL")]
+    wallet_url: Option<String>,
+    /// A file containing the API token for the vega wallet service,
+    /// the token can also be set with VEGAMM_WALLET_TOKEN
+    #[arg(long, env = "VEGAMM_WALLET_TOKEN_FILE")]
+    wallet_token_file: Option<String>,
+    /// A Vega public key to be used to submit transactions
+    #[arg(long, env = "VEGAMM_WALLET_PUBKEY")]
+    wallet_pubkey: Option<String>,
+    /// An ID of a market in Vega
+    #[arg(long, env = "VEGAMM_VEGA_MARKET")]
+    vega_market: Option<String>,
+    /// Binance websocket url
+    #[arg(long, env = "VEGAMM_BINANCE_WS_URL")]
+    binance_ws_url: Option<String>,
+    /// Binance REST API url, used to snapshot the order book
+    #[arg(long, env = "VEGAMM_BINANCE_REST_URL")]
+    binance_rest_url: Option<String>,
+    /// Coinbase websocket url
+    #[arg(long, env = "VEGAMM_COINBASE_WS_URL")]
+    coinbase_ws_url: Option<String>,
+    /// Kraken websocket url
+    #[arg(long, env = "VEGAMM_KRAKEN_WS_URL")]
+    kraken_ws_url: Option<String>,
+    /// Bybit websocket url
+    #[arg(long, env = "VEGAMM_BYBIT_WS_URL")]
+    bybit_ws_url: Option<String>,
+    /// An Binance market symbol
+    #[arg(long, env = "VEGAMM_BINANCE_MARKET")]
+    binance_market: Option<String>,
+    /// A Coinbase product ID
+    #[arg(long, env = "VEGAMM_COINBASE_PRODUCT")]
+    coinbase_product: Option<String>,
+    /// A Kraken pair name
+    #[arg(long, env = "VEGAMM_KRAKEN_PAIR")]
+    kraken_pair: Option<String>,
+    /// A Bybit spot market symbol
+    #[arg(long, env = "VEGAMM_BYBIT_SYMBOL")]
+    bybit_symbol: Option<String>,
+    /// Weight of the Binance reference in the weighted aggregation
+    #[arg(long, env = "VEGAMM_BINANCE_WEIGHT")]
+    binance_weight: Option<f64>,
+    /// Weight of the Coinbase reference in the weighted aggregation
+    #[arg(long, env = "VEGAMM_COINBASE_WEIGHT")]
+    coinbase_weight: Option<f64>,
+    /// Weight of the Kraken reference in the weighted aggregation
+    #[arg(long, env = "VEGAMM_KRAKEN_WEIGHT")]
+    kraken_weight: Option<f64>,
+    /// Weight of the Bybit reference in the weighted aggregation
+    #[arg(long, env = "VEGAMM_BYBIT_WEIGHT")]
+    bybit_weight: Option<f64>,
+    /// Binance symbol to convert the reference through, as SYMBOL or SYMBOL:invert
+    #[arg(long, env = "VEGAMM_CROSS_RATE", value_delimiter = ',')]
+    cross_rate: Option<Vec<CrossRate>>,
+    /// How the venues references are combined
+    #[arg(long, env = "VEGAMM_AGGREGATION", value_enum)]
+    aggregation: Option<Aggregation>,
+    /// Maximum relative deviation of a venue mid from the median before it is rejected
+    #[arg(long, env = "VEGAMM_MAX_PRICE_DEVIATION")]
+    max_price_deviation: Option<f64>,
+    /// Minimum number of valid venues required to quote
+    #[arg(long, env = "VEGAMM_MIN_VENUES")]
+    min_venues: Option<usize>,
+    /// The Binance stream used as reference price
+    #[arg(long, env = "VEGAMM_BINANCE_STREAM", value_enum)]
+    binance_stream: Option<RefSource>,
+    /// Number of levels of the Binance partial depth stream, one of 5, 10 or 20
+    #[arg(long, env = "VEGAMM_BINANCE_DEPTH_LEVELS")]
+    binance_depth_levels: Option<u32>,
+    /// How the reference prices are derived from the venues books
+    #[arg(long, env = "VEGAMM_REFERENCE_PRICING", value_enum)]
+    reference_pricing: Option<RefPricing>,
+    /// Volume used by the book based reference pricings
+    #[arg(long, env = "VEGAMM_REFERENCE_VOLUME")]
+    reference_volume: Option<f64>,
+    /// Maximum age of the reference price in milliseconds before quotes are pulled
+    #[arg(long, env = "VEGAMM_MAX_PRICE_AGE_MS")]
+    max_price_age_ms: Option<u64>,
+    /// Time in milliseconds the reference feed can be down before the bot shuts down, 0 disables
+    #[arg(long, env = "VEGAMM_MAX_FEED_DOWN_MS")]
+    max_feed_down_ms: Option<u64>,
+    /// Time in milliseconds to wait for the orders to be cancelled on shutdown
+    #[arg(long, env = "VEGAMM_SHUTDOWN_TIMEOUT_MS")]
+    shutdown_timeout_ms: Option<u64>,
+    #[command(flatten)]
+    strategy: StrategyArgs,
+}
+
+// override the config fields with the flags which were set,
+// optional fields are set from the flag value
+macro_rules! overlay {
+    ($cfg:expr, $cli:expr, $($field:ident),+ $(,)?) => {
+        $(
+            if let Some(v) = $cli.$field.clone() {
+                $cfg.$field = v.into();
+            }
+        )+
+    };
+}
+
+pub fn load() -> Result<Config, Error> {
+    let cli = Cli::parse();
+
+    let mut cfg = match &cli.config {
+        Some(path) => toml::from_str::<Config>(&fs::read_to_string(path)?)?,
+        None => Config::default(),
+    };
+
+    overlay!(
+        cfg,
+        cli,
+        port,
+        api_host,
+        vega_grpc_url,
+        wallet_url,
+        wallet_pubkey,
+        vega_market,
+        binance_ws_url,
+        binance_rest_url,
+        coinbase_ws_url,
+        kraken_ws_url,
+        bybit_ws_url,
+        binance_weight,
+        coinbase_weight,
+        kraken_weight,
+        bybit_weight,
+        cross_rate,
+        aggregation,
+        max_price_deviation,
+        min_venues,
+        binance_stream,
+        binance_depth_levels,
+        reference_pricing,
+        reference_volume,
+        max_price_age_ms,
+        max_feed_down_ms,
+        shutdown_timeout_ms,
+        wallet_token_file,
+        binance_market,
+        coinbase_product,
+        kraken_pair,
+        bybit_symbol,
+    );
+    cli.strategy.apply(&mut cfg.strategy)?;
+
+    // the token is never accepted as a flag so it does not show up
+    // in the process list, the environment wins over the token file
+    if let Ok(token) = std::env::var(WALLET_TOKEN_ENV) {
+        cfg.wallet_token = Some(Secret(token));
+    } else if let Some(path) = &cfg.wallet_token_file {
+        cfg.wallet_token = Some(Secret(fs::read_to_string(path)?.trim().to_string()));
+    }
+    if let Ok(token) = std::env::var(API_TOKEN_ENV) {
+        cfg.api_token = Some(Secret(token));
+    }
+
+    cfg.validate()?;
+    return Ok(cfg);
+}
+
+impl Config {
+    pub fn wallet_token(&self) -> &str {
+        return self.wallet_token.as_ref().map(|t| t.expose()).unwrap_or("");
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut errors = vec![];
+
+        if self.wallet_pubkey.is_empty() {
+            errors.push("wallet_pubkey is required".to_string());
+        }
+        if self.vega_market.is_empty() {
+            errors.push("vega_market is required".to_string());
+        }
+        if let Some(token) = &self.api_token {
+            if token.expose().is_empty() {
+                errors.push("api_token must not be empty".to_string());
+            }
+        }
+        if self.wallet_token().is_empty() {
+            errors.push(format!(
+                "wallet token is required, from {}, wallet_token_file or wallet_token",
+                WALLET_TOKEN_ENV
+            ));
+        }
+
+        if let Err(e) = self.api_host.parse::<IpAddr>() {
+            errors.push(format!(
+                "api_host is not a valid ip address ({}): {}",
+                e, self.api_host
+            ));
+        }
+
+        for (name, url) in [
+            ("vega_grpc_url", &self.vega_grpc_url),
+            ("wallet_url", &self.wallet_url),
+            ("binance_ws_url", &self.binance_ws_url),
+            ("binance_rest_url", &self.binance_rest_url),
+            ("coinbase_ws_url", &self.coinbase_ws_url),
+            ("kraken_ws_url", &self.kraken_ws_url),
+            ("bybit_ws_url", &self.bybit_ws_url),
+        ] {
+            if let Err(e) = url.parse::<Url>() {
+                errors.push(format!("{} is not a valid url ({}): {}", name, e, url));
+            }
+        }
+
+        let venues = [
+            (&self.binance_market, self.binance_weight, "binance"),
+            (&self.coinbase_product, self.coinbase_weight, "coinbase"),
+            (&self.kraken_pair, self.kraken_weight, "kraken"),
+            (&self.bybit_symbol, self.bybit_weight, "bybit"),
+        ];
+        let configured = venues.iter().filter(|(m, _, _)| m.is_some()).count();
+        if configured == 0 {
+            errors.push(
+                "at least one of binance_market, coinbase_product, kraken_pair or bybit_symbol is required"
+                    .to_string(),
+            );
+        }
+        for (_, weight, name) in venues.iter() {
+            if *weight <= 0. {
+                errors.push(format!("{}_weight must be positive", name));
+            }
+        }
+
+        if self.min_venues == 0 || self.min_venues > configured {
+            errors.push(format!(
+                "min_venues must be between 1 and the {} configured venues",
+                configured
+            ));
+        }
+        if self.max_price_deviation <= 0. {
+            errors.push("max_price_deviation must be positive".to_string());
+        }
+        if ![5, 10, 20].contains(&self.binance_depth_levels) {
+            errors.push("binance_depth_levels must be one of 5, 10 or 20".to_string());
+        }
+        if self.reference_volume < 0. {
+            errors.push("reference_volume must not be negative".to_string());
+        }
+        if self.max_price_age_ms == 0 {
+            errors.push("max_price_age_ms must not be 0".to_string());
+        }
+        if self.shutdown_timeout_ms == 0 {
+            errors.push("shutdown_timeout_ms must not be 0".to_string());
+        }
+
+        errors.append(&mut self.strategy.validate());
+
+        if !errors.is_empty() {
+            return Err(Error::Invalid(errors));
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Strategy(strategy_config::Error),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "config error: {}", self.desc())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(error: toml::de::Error) -> Self {
+        Error::Toml(error)
+    }
+}
+
+impl From<strategy_config::Error> for Error {
+    fn from(error: strategy_config::Error) -> Self {
+        Error::Strategy(error)
+    }
+}
+
+impl StdError for Error {}
+
+impl Error {
+    pub fn desc(&self) -> String {
+        use Error::*;
+        match self {
+            Io(e) => format!("could not read file: {}", e),
+            Toml(e) => format!("invalid TOML: {}", e),
+            Strategy(e) => format!("{}", e),
+            Invalid(errors) => format!("invalid fields:\n  - {}", errors.join("\n  - ")),
+        }
+    }
+}
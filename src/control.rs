@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use crate::strategy_config::StrategyConfig;
+
+/// Runtime controls of the bot, set from the API and
+/// applied by the strategy on its next run
+pub struct Control {
+    paused: bool,
+    cancel_all: bool,
+    // latest accepted parameters
+    cfg: StrategyConfig,
+    // parameters not picked up by the strategy yet
+    pending: Option<StrategyConfig>,
+}
+
+/// Strategy parameters which can be changed at runtime,
+/// the ones not set are left unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParamsUpdate {
+    pub levels: Option<usize>,
+    pub first_level_offset: Option<f64>,
+    pub level_step: Option<f64>,
+    pub balance_fraction: Option<f64>,
+    pub refresh_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ControlView {
+    pub paused: bool,
+    // true until the strategy picked up the last parameters update
+    pub update_pending: bool,
+    pub params: StrategyConfig,
+}
+
+impl Control {
+    pub fn new(cfg: StrategyConfig) -> Control {
+        return Control {
+            paused: false,
+            cancel_all: false,
+            cfg,
+            pending: None,
+        };
+    }
+
+    pub fn view(&self) -> ControlView {
+        return ControlView {
+            paused: self.paused,
+            update_pending: self.pending.is_some(),
+            params: self.cfg.clone(),
+        };
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.paused;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn request_cancel_all(&mut self) {
+        self.cancel_all = true;
+    }
+
+    // returns true once per cancel all request
+    pub fn take_cancel_all(&mut self) -> bool {
+        return std::mem::take(&mut self.cancel_all);
+    }
+
+    // returns a description of every invalid parameter if the
+    // update is rejected, in which case nothing is changed
+    pub fn update(&mut self, update: &ParamsUpdate) -> Result<(), Vec<String>> {
+        let mut cfg = self.cfg.clone();
+        if let Some(v) = update.levels {
+            cfg.levels = v;
+        }
+        if let Some(v) = update.first_level_offset {
+            cfg.first_level_offset = v;
+        }
+        if let Some(v) = update.level_step {
+            cfg.level_step = v;
+        }
+        if let Some(v) = update.balance_fraction {
+            cfg.balance_fraction = v;
+        }
+        if let Some(v) = update.refresh_interval_ms {
+            cfg.refresh_interval_ms = v;
+        }
+
+        let errors = cfg.validate();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.cfg = cfg.clone();
+        self.pending = Some(cfg);
+        return Ok(());
+    }
+
+    pub fn take_update(&mut self) -> Option<StrategyConfig> {
+        return self.pending.take();
+    }
+}
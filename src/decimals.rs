@@ -0,0 +1,280 @@
+use num_traits::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+use vega_protobufs::vega::{Asset, Market};
+
+// largest scale a decimal can represent
+const MAX_DECIMALS: u32 = 28;
+
+/// How a value is rounded when converted to a Vega precision
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    // towards negative infinity, used for bids and sizes
+    Floor,
+    // towards positive infinity, used for asks
+    Ceil,
+}
+
+impl Rounding {
+    fn strategy(&self) -> RoundingStrategy {
+        return match self {
+            Rounding::Floor => RoundingStrategy::ToNegativeInfinity,
+            Rounding::Ceil => RoundingStrategy::ToPositiveInfinity,
+        };
+    }
+}
+
+/// Converts between human units and the integer precisions used by Vega
+/// for the market prices, the market positions and the asset amounts
+pub struct Decimals {
+    position_decimals: u32,
+    price_decimals: u32,
+    asset_decimals: u32,
+}
+
+impl Decimals {
+    pub fn new(mkt: &Market, asset: &Asset) -> Decimals {
+        return Decimals {
+            position_decimals: mkt.position_decimal_places as u32,
+            price_decimals: mkt.decimal_places as u32,
+            asset_decimals: asset.details.as_ref().unwrap().decimals as u32,
+        };
+    }
+
+    pub fn from_asset_precision(&self, amount: &str) -> Result<Decimal, Error> {
+        return from_precision(amount, self.asset_decimals);
+    }
+
+    pub fn from_market_price_precision(&self, price: &str) -> Result<Decimal, Error> {
+        return from_precision(price, self.price_decimals);
+    }
+
+    pub fn from_market_position_precision(&self, position: i64) -> Result<Decimal, Error> {
+        return from_precision(&position.to_string(), self.position_decimals);
+    }
+
+    pub fn to_market_price_precision(
+        &self,
+        price: Decimal,
+        rounding: Rounding,
+    ) -> Result<String, Error> {
+        let p = to_precision(price, self.price_decimals, rounding)?;
+        if p.is_sign_negative() && !p.is_zero() {
+            return Err(Error::Negative(price));
+        }
+        return Ok(p.to_string());
+    }
+
+    pub fn to_market_position_precision(
+        &self,
+        position: Decimal,
+        rounding: Rounding,
+    ) -> Result<u64, Error> {
+        let p = to_precision(position, self.position_decimals, rounding)?;
+        if p.is_sign_negative() && !p.is_zero() {
+            return Err(Error::Negative(position));
+        }
+        return p.to_u64().ok_or(Error::Overflow(position.to_string()));
+    }
+}
+
+/// Converts a float computed by the strategy to a decimal
+pub fn from_f64(v: f64) -> Result<Decimal, Error> {
+    return Decimal::from_f64(v).ok_or(Error::NotFinite(v));
+}
+
+// parse an integer in a precision and scale it down to human units
+fn from_precision(value: &str, decimals: u32) -> Result<Decimal, Error> {
+    let mut d = Decimal::from_str(value).map_err(|_| Error::Overflow(value.to_string()))?;
+    if d.scale() != 0 {
+        return Err(Error::InvalidInteger(value.to_string()));
+    }
+    d.set_scale(decimals)
+        .map_err(|_| Error::Overflow(value.to_string()))?;
+    return Ok(d);
+}
+
+// scale a human value up to a precision and round it to an integer
+fn to_precision(value: Decimal, decimals: u32, rounding: Rounding) -> Result<Decimal, Error> {
+    if decimals > MAX_DECIMALS {
+        return Err(Error::Overflow(value.to_string()));
+    }
+    let factor = Decimal::from_i128_with_scale(10_i128.pow(decimals), 0);
+    let scaled = value
+        .checked_mul(factor)
+        .ok_or(Error::Overflow(value.to_string()))?;
+    return Ok(scaled
+        .round_dp_with_strategy(0, rounding.strategy())
+        .normalize());
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Overflow(String),
+    Negative(Decimal),
+    NotFinite(f64),
+    InvalidInteger(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decimals error: {}", self.desc())
+    }
+}
+
+impl StdError for Error {}
+
+impl Error {
+    pub fn desc(&self) -> String {
+        use Error::*;
+        match self {
+            Overflow(v) => format!("value out of range: {}", v),
+            Negative(v) => format!("negative value cannot be sent to vega: {}", v),
+            NotFinite(v) => format!("value is not a finite number: {}", v),
+            InvalidInteger(v) => format!("expected an integer amount: {}", v),
+        }
+    }
+}
+
+// fixtures shared by the tests of the modules converting to vega
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use vega_protobufs::vega::AssetDetails;
+
+    pub fn dec(s: &str) -> Decimal {
+        return Decimal::from_str(s).unwrap();
+    }
+
+    // prices, sizes and amounts without decimals on vega
+    pub fn without_decimals() -> (Market, Asset, Decimals) {
+        let market = Market::default();
+        let asset = Asset {
+            details: Some(AssetDetails::default()),
+            ..Default::default()
+        };
+        let decimals = Decimals::new(&market, &asset);
+        return (market, asset, decimals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::dec;
+    use super::*;
+
+    fn decimals(price: u32, position: u32, asset: u32) -> Decimals {
+        return Decimals {
+            position_decimals: position,
+            price_decimals: price,
+            asset_decimals: asset,
+        };
+    }
+
+    #[test]
+    fn price_is_rounded_explicitly() {
+        let d = decimals(2, 0, 0);
+        let floor = d.to_market_price_precision(dec("1.239"), Rounding::Floor);
+        let ceil = d.to_market_price_precision(dec("1.231"), Rounding::Ceil);
+        assert_eq!(floor.unwrap(), "123");
+        assert_eq!(ceil.unwrap(), "124");
+
+        // already on the tick
+        let exact = d.to_market_price_precision(dec("1.23"), Rounding::Ceil);
+        assert_eq!(exact.unwrap(), "123");
+    }
+
+    #[test]
+    fn size_is_floored() {
+        let d = decimals(0, 3, 0);
+        let size = d.to_market_position_precision(dec("0.0129"), Rounding::Floor);
+        assert_eq!(size.unwrap(), 12);
+    }
+
+    #[test]
+    fn negative_values_are_rejected() {
+        let d = decimals(2, 3, 0);
+        assert!(matches!(
+            d.to_market_price_precision(dec("-1"), Rounding::Ceil),
+            Err(Error::Negative(_))
+        ));
+        assert!(matches!(
+            d.to_market_position_precision(dec("-0.5"), Rounding::Floor),
+            Err(Error::Negative(_))
+        ));
+        // floored below zero
+        assert!(matches!(
+            d.to_market_price_precision(dec("-0.001"), Rounding::Floor),
+            Err(Error::Negative(_))
+        ));
+        // ceiled up to zero
+        let zero = d.to_market_price_precision(dec("-0.001"), Rounding::Ceil);
+        assert_eq!(zero.unwrap(), "0");
+    }
+
+    #[test]
+    fn negative_positions_are_read() {
+        let d = decimals(0, 3, 0);
+        assert_eq!(
+            d.from_market_position_precision(-1500).unwrap(),
+            dec("-1.5")
+        );
+    }
+
+    #[test]
+    fn eighteen_decimals_assets() {
+        let d = decimals(18, 0, 18);
+        assert_eq!(
+            d.from_asset_precision("1000000000000000000").unwrap(),
+            Decimal::ONE
+        );
+        assert_eq!(
+            d.from_asset_precision("123456789012345678901234567")
+                .unwrap(),
+            dec("123456789.012345678901234567")
+        );
+        let price = d.to_market_price_precision(dec("1.5"), Rounding::Floor);
+        assert_eq!(price.unwrap(), "1500000000000000000");
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        let d = decimals(2, 3, 0);
+        assert!(matches!(
+            d.to_market_price_precision(Decimal::MAX, Rounding::Floor),
+            Err(Error::Overflow(_))
+        ));
+        // does not fit in a u64 size
+        assert!(matches!(
+            d.to_market_position_precision(dec("100000000000000000000"), Rounding::Floor),
+            Err(Error::Overflow(_))
+        ));
+        // more decimals than a decimal can hold
+        assert!(matches!(
+            decimals(29, 0, 0).to_market_price_precision(Decimal::ONE, Rounding::Floor),
+            Err(Error::Overflow(_))
+        ));
+        assert!(matches!(
+            d.from_asset_precision("not a number"),
+            Err(Error::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn amounts_must_be_integers() {
+        let d = decimals(2, 3, 6);
+        assert!(matches!(
+            d.from_asset_precision("1.5"),
+            Err(Error::InvalidInteger(_))
+        ));
+    }
+
+    #[test]
+    fn floats_must_be_finite() {
+        assert!(matches!(from_f64(f64::NAN), Err(Error::NotFinite(_))));
+        assert!(matches!(from_f64(f64::INFINITY), Err(Error::NotFinite(_))));
+        assert_eq!(from_f64(0.5).unwrap(), dec("0.5"));
+    }
+}
@@ -0,0 +1,27 @@
+use tokio::sync::broadcast;
+
+// slow receivers skip the oldest events past this
+const CAPACITY: usize = 1024;
+
+/// Things happening in the bot which may warrant an update of the quotes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // the aggregated reference price changed
+    ReferencePrice { bid: f64, ask: f64 },
+    // one of our orders traded, size in market precision
+    Fill { order_id: String, size: u64 },
+    // our open volume on the market changed
+    Position { open_volume: i64 },
+    // the market trading mode changed
+    TradingMode { mode: i32 },
+    // the bot was paused, resumed or reconfigured from the API
+    Control,
+}
+
+pub type Sender = broadcast::Sender<Event>;
+pub type Receiver = broadcast::Receiver<Event>;
+
+pub fn channel() -> Sender {
+    let (tx, _) = broadcast::channel(CAPACITY);
+    return tx;
+}
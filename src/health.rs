@@ -0,0 +1,150 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use vega_protobufs::vega::market::TradingMode;
+
+use crate::ref_price::{FeedState, RefPrice};
+use crate::vega_store::VegaStore;
+
+// slack given to the strategy on top of its refresh interval,
+// covering a slow batch submission
+const STRATEGY_GRACE: Duration = Duration::from_secs(30);
+// how long a request can wait for the wallet before it is considered down
+const WALLET_MAX_SILENCE: Duration = Duration::from_secs(60);
+
+/// What the liveness and readiness probes are computed from
+pub struct Health {
+    // background tasks which are never expected to return
+    tasks: Vec<(String, JoinHandle<()>)>,
+    // last strategy run and the interval until the next one
+    strategy_ran_at: Option<Instant>,
+    strategy_interval: Duration,
+    // whether the last request to the wallet succeeded
+    wallet_ok: Option<bool>,
+    wallet_responded_at: Option<Instant>,
+    // oldest request still waiting for the wallet, the wallet is
+    // only asked when the quotes change so it can be silent when idle
+    wallet_waiting_since: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Health {
+    pub fn new() -> Health {
+        return Health {
+            tasks: vec![],
+            strategy_ran_at: None,
+            strategy_interval: Duration::ZERO,
+            wallet_ok: None,
+            wallet_responded_at: None,
+            wallet_waiting_since: None,
+        };
+    }
+
+    pub fn watch(&mut self, name: &str, task: JoinHandle<()>) {
+        self.tasks.push((name.to_string(), task));
+    }
+
+    pub fn strategy_ran(&mut self, interval: Duration) {
+        self.strategy_ran_at = Some(Instant::now());
+        self.strategy_interval = interval;
+    }
+
+    pub fn wallet_requested(&mut self) {
+        if self.wallet_waiting_since.is_none() {
+            self.wallet_waiting_since = Some(Instant::now());
+        }
+    }
+
+    pub fn wallet_responded(&mut self, ok: bool) {
+        self.wallet_ok = Some(ok);
+        self.wallet_responded_at = Some(Instant::now());
+        self.wallet_waiting_since = None;
+    }
+
+    /// The process should be restarted if any of these fails
+    pub fn liveness(&self) -> Vec<Check> {
+        let mut checks = vec![];
+        for (name, task) in self.tasks.iter() {
+            let alive = !task.is_finished();
+            checks.push(Check {
+                name: format!("task {}", name),
+                ok: alive,
+                detail: if alive { "running" } else { "stopped" }.to_string(),
+            });
+        }
+
+        // the strategy did not run yet while the bot is starting
+        let (ok, detail) = match self.strategy_ran_at {
+            Some(t) => {
+                let since = t.elapsed();
+                (
+                    since <= self.strategy_interval * 2 + STRATEGY_GRACE,
+                    format!("last run {:?} ago", since),
+                )
+            }
+            None => (true, "not started yet".to_string()),
+        };
+        checks.push(Check {
+            name: "strategy".to_string(),
+            ok,
+            detail,
+        });
+        return checks;
+    }
+
+    /// The bot can quote only if all of these pass
+    pub fn readiness(
+        &self,
+        store: &VegaStore,
+        rp: &RefPrice,
+        max_price_age: Duration,
+    ) -> Vec<Check> {
+        let mut checks = vec![];
+
+        let state = rp.get_state();
+        let fresh = state == FeedState::Connected && !rp.is_stale(max_price_age);
+        checks.push(Check {
+            name: "reference feed".to_string(),
+            ok: fresh,
+            detail: format!("{:?}, age {:?}", state, rp.get_age()),
+        });
+
+        for (stream, status) in store.get_streams().iter() {
+            checks.push(Check {
+                name: format!("{} stream", stream),
+                ok: status.state == FeedState::Connected,
+                detail: format!("{:?}", status.state),
+            });
+        }
+
+        let waiting = self.wallet_waiting_since.map(|t| t.elapsed());
+        let silent = waiting.map_or(false, |w| w > WALLET_MAX_SILENCE);
+        checks.push(Check {
+            name: "wallet".to_string(),
+            ok: self.wallet_ok == Some(true) && !silent,
+            detail: match (self.wallet_ok, self.wallet_responded_at, waiting) {
+                (_, _, Some(w)) if silent => format!("no response for {:?}", w),
+                (Some(ok), Some(t), _) => format!(
+                    "last request {} {:?} ago",
+                    if ok { "succeeded" } else { "failed" },
+                    t.elapsed()
+                ),
+                _ => "never responded".to_string(),
+            },
+        });
+
+        let mode = TradingMode::from_i32(store.get_market_data().market_trading_mode);
+        checks.push(Check {
+            name: "trading mode".to_string(),
+            ok: mode == Some(TradingMode::Continuous),
+            detail: format!("{:?}", mode),
+        });
+        return checks;
+    }
+}
@@ -0,0 +1,103 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Mutex;
+
+use crate::price_source::{parse_price, parse_volume, Error, Handled, PriceSource};
+use crate::ref_price::RefPrice;
+
+pub struct KrakenSource {
+    ws_url: String,
+    pair: String,
+}
+
+impl KrakenSource {
+    pub fn new(ws_url: String, pair: String) -> KrakenSource {
+        return KrakenSource { ws_url, pair };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Subscription {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    event: String,
+    pair: Vec<String>,
+    subscription: Subscription,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EventResponse {
+    event: String,
+    status: Option<String>,
+    #[serde(rename = "errorMessage")]
+    error_message: Option<String>,
+}
+
+// channel messages are arrays:
+// [channelID, [bid, ask, timestamp, bidVolume, askVolume], "spread", pair]
+fn parse_spread(v: &Value) -> Option<(f64, f64, f64, f64, u64)> {
+    let s = v.as_array()?.get(1)?.as_array()?;
+    let field = |i: usize, parse: fn(&str) -> Result<f64, Error>| parse(s.get(i)?.as_str()?).ok();
+    let ts = s.get(2)?.as_str()?.parse::<f64>().ok()?;
+    return Some((
+        field(0, parse_price)?,
+        field(3, parse_volume)?,
+        field(1, parse_price)?,
+        field(4, parse_volume)?,
+        (ts * 1000.) as u64,
+    ));
+}
+
+impl PriceSource for KrakenSource {
+    fn name(&self) -> String {
+        return format!("kraken({})", self.pair);
+    }
+
+    fn url(&self) -> String {
+        return self.ws_url.clone();
+    }
+
+    fn subscriptions(&self) -> Result<Vec<String>, Error> {
+        return Ok(vec![serde_json::to_string(&Request {
+            event: "subscribe".to_string(),
+            pair: vec![self.pair.clone()],
+            subscription: Subscription {
+                name: "spread".to_string(),
+            },
+        })?]);
+    }
+
+    fn handle_message(&mut self, text: &str, rp: &Mutex<RefPrice>) -> Result<Handled, Error> {
+        let v = serde_json::from_str::<Value>(text)?;
+        if v.is_object() {
+            let r = serde_json::from_value::<EventResponse>(v)?;
+            return match (r.event.as_str(), r.status.as_deref()) {
+                ("subscriptionStatus", Some("subscribed")) => Ok(Handled::Subscribed),
+                ("subscriptionStatus", _) => {
+                    info!("kraken subscription error: {:?}", r.error_message);
+                    Err(Error::InvalidMessage)
+                }
+                // the spread is only pushed when it changes, the heartbeats
+                // sent every second show a quiet pair is still streamed
+                ("heartbeat", _) => {
+                    rp.lock().unwrap().refresh();
+                    Ok(Handled::Ignored)
+                }
+                // system status
+                _ => Ok(Handled::Ignored),
+            };
+        }
+
+        let (bid, bid_volume, ask, ask_volume, event_time) =
+            parse_spread(&v).ok_or(Error::InvalidMessage)?;
+        info!("new kraken prices: bid({}), ask({})", bid, ask);
+        rp.lock()
+            .unwrap()
+            .set(bid, bid_volume, ask, ask_volume, Some(event_time));
+        return Ok(Handled::Price);
+    }
+}
@@ -0,0 +1,113 @@
+use log::info;
+use num_traits::ToPrimitive;
+use rust_decimal::Decimal;
+use vega_wallet_client::commands::Side;
+
+use crate::{
+    decimals,
+    skew::Skew,
+    strategy::{Error, Quote, Snapshot, Strategy},
+    strategy_config::StrategyConfig,
+};
+
+/// The default strategy, a ladder of orders at fixed offsets around the
+/// reference bid and ask, leaning out of the inventory with the skew
+pub struct LadderStrategy {
+    cfg: StrategyConfig,
+}
+
+impl LadderStrategy {
+    pub fn new(cfg: StrategyConfig) -> LadderStrategy {
+        return LadderStrategy { cfg };
+    }
+}
+
+impl Strategy for LadderStrategy {
+    fn name(&self) -> &'static str {
+        return "ladder";
+    }
+
+    fn set_config(&mut self, cfg: StrategyConfig) {
+        self.cfg = cfg;
+    }
+
+    fn quotes(&mut self, snap: &Snapshot) -> Result<Vec<Quote>, Error> {
+        let (bid_volume, offer_volume) = side_volumes(&self.cfg, snap)?;
+        let (open_volume, _) = snap.position()?;
+
+        let skew = Skew::new(&self.cfg, open_volume.to_f64().unwrap_or(0.));
+        info!("inventory skew: {:?}", skew);
+        let mid_shift = Decimal::ONE + decimals::from_f64(skew.mid)?;
+        let first_offset = decimals::from_f64(self.cfg.first_level_offset)?;
+
+        let mut quotes = levels(
+            &self.cfg,
+            Side::Buy,
+            decimals::from_f64(snap.best_bid)? * mid_shift,
+            first_offset + decimals::from_f64(skew.bid_offset)?,
+            bid_volume,
+        )?;
+        quotes.append(&mut levels(
+            &self.cfg,
+            Side::Sell,
+            decimals::from_f64(snap.best_ask)? * mid_shift,
+            first_offset + decimals::from_f64(skew.ask_offset)?,
+            offer_volume,
+        )?);
+        return Ok(quotes);
+    }
+}
+
+/// Volume allocated to the bid and ask sides, from the balance
+/// and reduced on the side adding to the current position
+pub fn side_volumes(cfg: &StrategyConfig, snap: &Snapshot) -> Result<(Decimal, Decimal), Error> {
+    let (open_volume, aep) = snap.position()?;
+    let balance = snap.balance()?;
+    info!("pubkey balance: {}", balance);
+
+    let fraction = decimals::from_f64(cfg.balance_fraction)?;
+    let bid_volume = balance * fraction - open_volume * aep;
+    let offer_volume = balance * fraction + open_volume * aep;
+    let notional_exposure = (open_volume * aep).abs();
+    info!(
+        "openvolume({}), entryPrice({}), notionalExposure({})",
+        open_volume, aep, notional_exposure,
+    );
+    info!("bidVolume({}), offerVolume({})", bid_volume, offer_volume);
+    return Ok((bid_volume, offer_volume));
+}
+
+/// The levels of one side, starting at the first offset from the
+/// reference price and spaced by the level step
+pub fn levels(
+    cfg: &StrategyConfig,
+    side: Side,
+    ref_price: Decimal,
+    first_offset: Decimal,
+    target_volume: Decimal,
+) -> Result<Vec<Quote>, Error> {
+    let sign = match side {
+        Side::Buy => -Decimal::ONE,
+        Side::Sell => Decimal::ONE,
+        _ => panic!("should never happen"),
+    };
+
+    // a side can be left without volume by the current position
+    let target_volume = target_volume.max(Decimal::ZERO);
+
+    let mut quotes = vec![];
+    for (i, weight) in cfg.level_weights().into_iter().enumerate() {
+        let size = target_volume * decimals::from_f64(weight)? * ref_price;
+        // the skew never moves a level through the reference price
+        let step = decimals::from_f64(cfg.level_step * i as f64)?;
+        let offset = (first_offset + step).max(Decimal::ZERO);
+
+        quotes.push(Quote {
+            side,
+            price: ref_price * (Decimal::ONE + sign * offset),
+            size,
+        });
+    }
+
+    return Ok(quotes);
+}
@@ -1,98 +1,252 @@
-use clap::Parser;
-use log::info;
+use log::{info, warn};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::time;
+use tokio::sync::watch;
 use vega_protobufs::datanode::api::v2::trading_data_service_client::TradingDataServiceClient;
 use vega_store::update_forever;
 
+use aggregator::Venue;
+use price_source::PriceSource;
+use ref_price::RefPrice;
+
+mod aggregator;
 mod api;
+mod avellaneda;
+mod backoff;
 mod binance_ws;
+mod bybit_ws;
+mod coinbase_ws;
+mod config;
+mod control;
+mod decimals;
+mod events;
+mod health;
+mod kraken_ws;
+mod ladder;
+mod metrics;
+mod order_book;
+mod pnl;
+mod price_source;
+mod push;
+mod reconciler;
+mod ref_price;
+mod risk;
+mod shutdown;
+mod skew;
 mod strategy;
+mod strategy_config;
 mod vega_store;
-
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    /// Port of the http API
-    #[arg(long, default_value_t = 8080)]
-    port: u16,
-    /// A vega grpc node address
-    #[arg(long, default_value_t = String::from("tcp://n11.testnet.vega.xyz:3007"))]
-    vega_grpc_url: String,
-    /// A vega wallet service address
-    #[arg(long, default_value_t = String::from("http://127.0.0.1:1789"))]
-    wallet_url: String,
-    /// Binance websocket url
-    #[arg(long, default_value_t = String::from("wss://stream.binance.com:443/ws"))]
-    binance_ws_url: String,
-    /// An API token for the vega wallet service
-    #[arg(long)]
-    wallet_token: String,
-    /// A Vega public key to be used to submit transactions
-    #[arg(long)]
-    wallet_pubkey: String,
-    /// An ID of a market in Vega
-    #[arg(long)]
-    vega_market: String,
-    /// An Binance market symbol
-    #[arg(long)]
-    binance_market: String,
-}
+mod views;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     pretty_env_logger::init();
-    let cli = Cli::parse();
+    let cfg = config::load()?;
+    info!("configuration: {:?}", cfg);
 
     info!("connecting with the go wallet service");
-    let wclt = vega_wallet_client::WalletClient::new(
-        &cli.wallet_url,
-        &cli.wallet_token,
-        &cli.wallet_pubkey,
-    )
-    .await?;
+    let wclt = Arc::new(
+        vega_wallet_client::WalletClient::new(
+            &cfg.wallet_url,
+            cfg.wallet_token(),
+            &cfg.wallet_pubkey,
+        )
+        .await?,
+    );
     info!("connection with the go wallet service successful");
 
-    let rp = Arc::new(Mutex::new(binance_ws::RefPrice::new()));
+    // the feeds, the aggregator and the strategy stop cooperatively
+    // once this is set or dropped
+    let (stop, stop_rx) = watch::channel(false);
+    let (kill, mut killed) = shutdown::KillSwitch::new();
+
+    // the wallet client responded when it was created
+    let health = Arc::new(Mutex::new(health::Health::new()));
+    health.lock().unwrap().wallet_responded(true);
+
+    let mut venues = vec![];
+    if let Some(mkt) = &cfg.binance_market {
+        let source = binance_ws::BinanceSource::new(binance_ws::FeedConfig {
+            ws_url: cfg.binance_ws_url.clone(),
+            rest_url: cfg.binance_rest_url.clone(),
+            market: mkt.clone(),
+            source: cfg.binance_stream,
+            depth_levels: cfg.binance_depth_levels,
+        });
+        venues.push(spawn_venue(source, cfg.binance_weight, &stop_rx, &health));
+    }
+    if let Some(product) = &cfg.coinbase_product {
+        let source = coinbase_ws::CoinbaseSource::new(cfg.coinbase_ws_url.clone(), product.clone());
+        venues.push(spawn_venue(source, cfg.coinbase_weight, &stop_rx, &health));
+    }
+    if let Some(pair) = &cfg.kraken_pair {
+        let source = kraken_ws::KrakenSource::new(cfg.kraken_ws_url.clone(), pair.clone());
+        venues.push(spawn_venue(source, cfg.kraken_weight, &stop_rx, &health));
+    }
+    if let Some(symbol) = &cfg.bybit_symbol {
+        let source = bybit_ws::BybitSource::new(cfg.bybit_ws_url.clone(), symbol.clone());
+        venues.push(spawn_venue(source, cfg.bybit_weight, &stop_rx, &health));
+    }
+
+    let mut legs = vec![];
+    for cross in cfg.cross_rate.iter() {
+        let source = binance_ws::BinanceSource::new(binance_ws::FeedConfig {
+            ws_url: cfg.binance_ws_url.clone(),
+            rest_url: cfg.binance_rest_url.clone(),
+            market: cross.symbol.clone(),
+            source: binance_ws::RefSource::BookTicker,
+            depth_levels: cfg.binance_depth_levels,
+        });
+        let venue = spawn_venue(source, 1., &stop_rx, &health);
+        legs.push(aggregator::CrossLeg {
+            name: venue.name,
+            invert: cross.invert,
+            rp: venue.rp,
+        });
+    }
+
+    // wakes the strategy on fills, position, trading mode and price changes
+    let events = events::channel();
+    // state changes pushed to the websocket clients
+    let push = push::channel();
 
-    tokio::spawn(binance_ws::start(
-        cli.binance_ws_url.clone(),
-        cli.binance_market.clone(),
+    let rp = Arc::new(Mutex::new(RefPrice::new()));
+    let aggregator = tokio::spawn(aggregator::start(
+        venues,
+        legs,
+        aggregator::AggregatorConfig {
+            method: cfg.aggregation,
+            max_deviation: cfg.max_price_deviation,
+            min_venues: cfg.min_venues,
+            max_price_age: Duration::from_millis(cfg.max_price_age_ms),
+            pricing: cfg.reference_pricing,
+            pricing_volume: cfg.reference_volume,
+        },
         rp.clone(),
+        events.clone(),
+        stop_rx.clone(),
     ));
+    health.lock().unwrap().watch("aggregator", aggregator);
 
-    let addr = cli.vega_grpc_url.clone();
+    let addr = cfg.vega_grpc_url.clone();
     let mut tdclt = TradingDataServiceClient::connect(addr).await?;
 
     let vstore = Arc::new(Mutex::new(
-        vega_store::VegaStore::new(&mut tdclt, &*cli.vega_market, &*cli.wallet_pubkey).await?,
+        vega_store::VegaStore::new(
+            &mut tdclt,
+            &*cfg.vega_market,
+            &*cfg.wallet_pubkey,
+            events.clone(),
+            push.clone(),
+        )
+        .await?,
     ));
 
-    update_forever(
+    let pnl = Arc::new(Mutex::new(pnl::Pnl::new(
+        &cfg.wallet_pubkey,
+        &vstore.lock().unwrap(),
+        rp.clone(),
+    )?));
+    let pnl_logger = tokio::spawn(pnl::start(pnl.clone(), vstore.clone()));
+    health.lock().unwrap().watch("pnl", pnl_logger);
+
+    let streams = update_forever(
         vstore.clone(),
         tdclt,
-        &*cli.vega_market,
-        &*cli.wallet_pubkey,
+        &*cfg.vega_market,
+        &*cfg.wallet_pubkey,
     );
+    for (stream, task) in streams.into_iter() {
+        health
+            .lock()
+            .unwrap()
+            .watch(&format!("{} stream", stream), task);
+    }
 
-    tokio::spawn(api::start(cli.port, vstore.clone(), rp.clone()));
+    let control = Arc::new(Mutex::new(control::Control::new(cfg.strategy.clone())));
+    let metrics = Arc::new(Mutex::new(metrics::Metrics::new()));
+    let api = tokio::spawn(api::start(
+        cfg.api_host.parse()?,
+        cfg.port,
+        api::Context {
+            store: vstore.clone(),
+            rp: rp.clone(),
+            pnl: pnl.clone(),
+            control: control.clone(),
+            metrics: metrics.clone(),
+            events: events.clone(),
+            push: push.clone(),
+            health: health.clone(),
+            max_price_age: Duration::from_millis(cfg.max_price_age_ms),
+            token: cfg.api_token.clone(),
+        },
+    ));
+    health.lock().unwrap().watch("api", api);
 
-    tokio::spawn(strategy::start(
-        wclt,
-        cli.wallet_pubkey.clone(),
-        cli.vega_market.clone(),
-        vstore.clone(),
-        rp.clone(),
+    let mut strategy = tokio::spawn(strategy::start(
+        strategy::Runner {
+            clt: wclt.clone(),
+            pubkey: cfg.wallet_pubkey.clone(),
+            market: cfg.vega_market.clone(),
+            store: vstore.clone(),
+            rp: rp.clone(),
+            max_price_age: Duration::from_millis(cfg.max_price_age_ms),
+            max_feed_down: match cfg.max_feed_down_ms {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
+            cfg: cfg.strategy.clone(),
+            kill,
+            control,
+            metrics,
+            push,
+            health,
+        },
+        events.subscribe(),
+        stop_rx,
     ));
 
-    // just loop forever, waiting for user interupt
-    let mut interval = time::interval(Duration::from_secs(1));
-    loop {
-        tokio::select! {
-            _ = interval.tick() => {
-                interval.reset();
-            }
-        }
+    // wait for a signal or the kill switch
+    let reason = tokio::select! {
+        reason = shutdown::signal_received() => reason,
+        Some(reason) = killed.recv() => reason,
+    };
+    warn!("shutting down: {}", reason);
+
+    // stop the strategy first so it does not requote behind our back
+    let _ = stop.send(true);
+    let timeout = Duration::from_millis(cfg.shutdown_timeout_ms);
+    if tokio::time::timeout(timeout, &mut strategy).await.is_err() {
+        // a batch it still has in flight must not land after the cancellation
+        warn!("strategy did not stop within {:?}, aborting it", timeout);
+        strategy.abort();
+        let _ = strategy.await;
+    }
+
+    let cancelled =
+        shutdown::cancel_all_and_wait(&wclt, &cfg.vega_market, vstore.clone(), timeout).await;
+    if !cancelled {
+        return Err("could not cancel all orders before shutting down".into());
     }
+    if reason.is_critical() {
+        return Err(format!("stopped by the kill switch: {}", reason).into());
+    }
+    info!("shutdown complete");
+    return Ok(());
+}
+
+fn spawn_venue<S: PriceSource>(
+    source: S,
+    weight: f64,
+    shutdown: &watch::Receiver<bool>,
+    health: &Mutex<health::Health>,
+) -> Venue {
+    let rp = Arc::new(Mutex::new(RefPrice::new()));
+    let name = source.name();
+    let feed = tokio::spawn(price_source::start(source, rp.clone(), shutdown.clone()));
+    health
+        .lock()
+        .unwrap()
+        .watch(&format!("{} feed", name), feed);
+    return Venue { name, weight, rp };
 }
@@ -0,0 +1,221 @@
+use num_traits::ToPrimitive;
+use std::fmt::Write;
+use std::time::Duration;
+use vega_protobufs::vega::AccountType;
+
+use crate::{
+    decimals::{self, Decimals},
+    ref_price::{FeedState, RefPrice},
+    vega_store::VegaStore,
+};
+
+// upper bounds of the batch latency histogram, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.];
+
+/// Counters updated by the bot, the gauges are read
+/// from the store and the reference price when scraped
+pub struct Metrics {
+    batches: u64,
+    batch_errors: u64,
+    // cumulative count of each latency bucket
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        return Metrics {
+            batches: 0,
+            batch_errors: 0,
+            latency_buckets: [0; LATENCY_BUCKETS.len()],
+            latency_sum: 0.,
+        };
+    }
+
+    pub fn observe_batch(&mut self, latency: Duration, ok: bool) {
+        let secs = latency.as_secs_f64();
+        self.batches += 1;
+        if !ok {
+            self.batch_errors += 1;
+        }
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if secs <= *bound {
+                self.latency_buckets[i] += 1;
+            }
+        }
+        self.latency_sum += secs;
+    }
+
+    /// Renders all the metrics in the prometheus text format
+    pub fn render(&self, store: &VegaStore, rp: &RefPrice) -> Result<String, decimals::Error> {
+        let mut out = Exposition::default();
+
+        let (bid, ask) = rp.get();
+        out.gauge(
+            "vegamm_reference_price",
+            "Aggregated reference price",
+            &[("side=\"bid\"", bid), ("side=\"ask\"", ask)],
+        );
+        out.gauge(
+            "vegamm_reference_price_age_seconds",
+            "Age of the reference price, -1 if none was received",
+            &[("", rp.get_age().map(|a| a.as_secs_f64()).unwrap_or(-1.))],
+        );
+        out.gauge(
+            "vegamm_reference_feed_up",
+            "Whether the reference feed is connected",
+            &[("", up(rp.get_state()))],
+        );
+        out.counter(
+            "vegamm_reference_feed_reconnects_total",
+            "Reconnections of the reference feed",
+            &[("", rp.get_reconnects() as f64)],
+        );
+
+        let market = store.get_market();
+        let asset = store.get_settlement_asset();
+        let decimals = Decimals::new(&market, &asset);
+
+        let md = store.get_market_data();
+        let mut prices = vec![];
+        for (label, price) in [
+            ("price=\"best_bid\"", &md.best_bid_price),
+            ("price=\"best_ask\"", &md.best_offer_price),
+            ("price=\"mark\"", &md.mark_price),
+        ] {
+            if !price.is_empty() {
+                prices.push((label, to_f64(decimals.from_market_price_precision(price)?)));
+            }
+        }
+        out.gauge("vegamm_vega_price", "Prices of the Vega market", &prices);
+
+        let position = match store.get_position() {
+            Some(p) => to_f64(decimals.from_market_position_precision(p.open_volume)?),
+            None => 0.,
+        };
+        out.gauge(
+            "vegamm_position",
+            "Open volume on the Vega market",
+            &[("", position)],
+        );
+
+        let mut balances = vec![];
+        for acc in store.get_accounts().iter() {
+            // the accounts can hold assets listed after the store was created
+            let asset = match store.get_asset(&acc.asset) {
+                Some(asset) => asset,
+                None => continue,
+            };
+            let decimals = Decimals::new(&market, &asset);
+            let account_type = match AccountType::from_i32(acc.r#type) {
+                Some(t) => format!("{:?}", t),
+                None => acc.r#type.to_string(),
+            };
+            balances.push((
+                format!(
+                    "type=\"{}\",asset=\"{}\",market=\"{}\"",
+                    account_type, acc.asset, acc.market_id
+                ),
+                to_f64(decimals.from_asset_precision(&acc.balance)?),
+            ));
+        }
+        out.gauge("vegamm_balance", "Balances of the accounts", &balances);
+
+        out.gauge(
+            "vegamm_live_orders",
+            "Live orders on the Vega market",
+            &[("", store.get_orders().len() as f64)],
+        );
+
+        let mut stream_up = vec![];
+        let mut stream_reconnects = vec![];
+        for (stream, status) in store.get_streams().iter() {
+            let label = format!("stream=\"{}\"", stream);
+            stream_up.push((label.clone(), up(status.state)));
+            stream_reconnects.push((label, status.reconnects as f64));
+        }
+        out.gauge(
+            "vegamm_vega_stream_up",
+            "Whether the data-node stream is connected",
+            &stream_up,
+        );
+        out.counter(
+            "vegamm_vega_stream_reconnects_total",
+            "Reconnections of the data-node streams",
+            &stream_reconnects,
+        );
+
+        out.counter(
+            "vegamm_batches_total",
+            "Batches of instructions sent to the wallet",
+            &[("", self.batches as f64)],
+        );
+        out.counter(
+            "vegamm_batch_errors_total",
+            "Batches of instructions the wallet failed to send",
+            &[("", self.batch_errors as f64)],
+        );
+        out.histogram(
+            "vegamm_batch_latency_seconds",
+            "Time to send a batch of instructions through the wallet",
+            &self.latency_buckets,
+            self.latency_sum,
+            self.batches,
+        );
+
+        return Ok(out.text);
+    }
+}
+
+// builds the text exposition format
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn gauge<L: AsRef<str>>(&mut self, name: &str, help: &str, samples: &[(L, f64)]) {
+        self.header(name, help, "gauge");
+        self.samples(name, samples);
+    }
+
+    fn counter<L: AsRef<str>>(&mut self, name: &str, help: &str, samples: &[(L, f64)]) {
+        self.header(name, help, "counter");
+        self.samples(name, samples);
+    }
+
+    fn histogram(&mut self, name: &str, help: &str, buckets: &[u64], sum: f64, count: u64) {
+        self.header(name, help, "histogram");
+        for (bound, n) in LATENCY_BUCKETS.iter().zip(buckets.iter()) {
+            let _ = writeln!(self.text, "{}_bucket{{le=\"{}\"}} {}", name, bound, n);
+        }
+        let _ = writeln!(self.text, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(self.text, "{}_sum {}", name, sum);
+        let _ = writeln!(self.text, "{}_count {}", name, count);
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn samples<L: AsRef<str>>(&mut self, name: &str, samples: &[(L, f64)]) {
+        for (labels, value) in samples.iter() {
+            let _ = match labels.as_ref() {
+                "" => writeln!(self.text, "{} {}", name, value),
+                labels => writeln!(self.text, "{}{{{}}} {}", name, labels, value),
+            };
+        }
+    }
+}
+
+fn up(state: FeedState) -> f64 {
+    return match state {
+        FeedState::Connected => 1.,
+        _ => 0.,
+    };
+}
+
+fn to_f64(v: rust_decimal::Decimal) -> f64 {
+    return v.to_f64().unwrap_or(f64::NAN);
+}
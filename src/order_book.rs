@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+// binance prices have at most 8 decimals, keys are prices scaled
+// to integers so levels can be ordered and matched exactly
+const PRICE_SCALE: f64 = 100_000_000.;
+
+fn key(price: f64) -> u64 {
+    return (price * PRICE_SCALE).round() as u64;
+}
+
+fn price(key: u64) -> f64 {
+    return key as f64 / PRICE_SCALE;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookSide {
+    Bid,
+    Ask,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    // key = scaled price, value = volume
+    bids: BTreeMap<u64, f64>,
+    asks: BTreeMap<u64, f64>,
+    last_update_id: u64,
+    // false until a snapshot is applied, or after a sequence gap
+    synced: bool,
+    // true once the first diff after the snapshot was applied
+    bridged: bool,
+}
+
+impl OrderBook {
+    pub fn new() -> OrderBook {
+        return OrderBook {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            last_update_id: 0,
+            synced: false,
+            bridged: false,
+        };
+    }
+
+    pub fn is_synced(&self) -> bool {
+        return self.synced;
+    }
+
+    pub fn apply_snapshot(
+        &mut self,
+        last_update_id: u64,
+        bids: &[(f64, f64)],
+        asks: &[(f64, f64)],
+    ) {
+        self.bids.clear();
+        self.asks.clear();
+        apply_levels(&mut self.bids, bids);
+        apply_levels(&mut self.asks, asks);
+        self.last_update_id = last_update_id;
+        self.synced = true;
+        self.bridged = false;
+    }
+
+    // apply a diff depth event following the binance sequencing rules,
+    // returns false if an update is missing and the book must be resynced
+    pub fn apply_diff(
+        &mut self,
+        first_update_id: u64,
+        final_update_id: u64,
+        bids: &[(f64, f64)],
+        asks: &[(f64, f64)],
+    ) -> bool {
+        if !self.synced {
+            return false;
+        }
+
+        // event is older than the snapshot
+        if final_update_id <= self.last_update_id {
+            return true;
+        }
+
+        let expected = self.last_update_id + 1;
+        let in_sequence = if self.bridged {
+            first_update_id == expected
+        } else {
+            first_update_id <= expected && final_update_id >= expected
+        };
+        if !in_sequence {
+            self.synced = false;
+            return false;
+        }
+
+        apply_levels(&mut self.bids, bids);
+        apply_levels(&mut self.asks, asks);
+        self.last_update_id = final_update_id;
+        self.bridged = true;
+        return true;
+    }
+
+    // apply levels for venues which do not sequence their updates
+    pub fn apply_update(&mut self, bids: &[(f64, f64)], asks: &[(f64, f64)]) {
+        apply_levels(&mut self.bids, bids);
+        apply_levels(&mut self.asks, asks);
+    }
+
+    pub fn best_bid(&self) -> Option<(f64, f64)> {
+        return self.bids.iter().next_back().map(|(k, v)| (price(*k), *v));
+    }
+
+    pub fn best_ask(&self) -> Option<(f64, f64)> {
+        return self.asks.iter().next().map(|(k, v)| (price(*k), *v));
+    }
+
+    // average price at which a volume would be filled against one
+    // side of the book, None if the book is not deep enough
+    pub fn fill_price(&self, side: BookSide, volume: f64) -> Option<f64> {
+        if volume <= 0. {
+            return match side {
+                BookSide::Bid => self.best_bid().map(|(p, _)| p),
+                BookSide::Ask => self.best_ask().map(|(p, _)| p),
+            };
+        }
+
+        let levels: Box<dyn Iterator<Item = (&u64, &f64)>> = match side {
+            BookSide::Bid => Box::new(self.bids.iter().rev()),
+            BookSide::Ask => Box::new(self.asks.iter()),
+        };
+
+        let (mut remaining, mut notional) = (volume, 0.);
+        for (k, v) in levels {
+            let filled = remaining.min(*v);
+            notional += filled * price(*k);
+            remaining -= filled;
+            if remaining <= 0. {
+                return Some(notional / volume);
+            }
+        }
+
+        return None;
+    }
+
+    // mid of the volume weighted prices needed to fill a volume
+    // on both sides of the book
+    pub fn vwap_mid(&self, volume: f64) -> Option<f64> {
+        let bid = self.fill_price(BookSide::Bid, volume)?;
+        let ask = self.fill_price(BookSide::Ask, volume)?;
+        return Some((bid + ask) / 2.);
+    }
+}
+
+fn apply_levels(side: &mut BTreeMap<u64, f64>, levels: &[(f64, f64)]) {
+    for (p, v) in levels.iter() {
+        if *v == 0. {
+            side.remove(&key(*p));
+        } else {
+            side.insert(key(*p), *v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synced_book() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.apply_snapshot(
+            100,
+            &[(10., 1.), (9.5, 2.), (9., 3.)],
+            &[(10.5, 1.), (11., 2.), (11.5, 3.)],
+        );
+        return book;
+    }
+
+    #[test]
+    fn snapshot_sets_the_top() {
+        let book = synced_book();
+        assert!(book.is_synced());
+        assert_eq!(book.best_bid(), Some((10., 1.)));
+        assert_eq!(book.best_ask(), Some((10.5, 1.)));
+    }
+
+    #[test]
+    fn diff_before_snapshot_requires_a_resync() {
+        let mut book = OrderBook::new();
+        assert!(!book.apply_diff(1, 2, &[(10., 1.)], &[]));
+        assert!(!book.is_synced());
+    }
+
+    #[test]
+    fn old_diffs_are_dropped() {
+        let mut book = synced_book();
+        assert!(book.apply_diff(90, 100, &[(10., 5.)], &[]));
+        assert_eq!(book.best_bid(), Some((10., 1.)));
+    }
+
+    #[test]
+    fn diffs_are_applied_in_sequence() {
+        let mut book = synced_book();
+        // the first diff straddles the snapshot
+        assert!(book.apply_diff(95, 105, &[(10., 0.), (10.2, 4.)], &[]));
+        assert_eq!(book.best_bid(), Some((10.2, 4.)));
+
+        assert!(book.apply_diff(106, 110, &[], &[(10.5, 0.)]));
+        assert_eq!(book.best_ask(), Some((11., 2.)));
+        assert!(book.is_synced());
+    }
+
+    #[test]
+    fn first_diff_after_the_snapshot_must_bridge_it() {
+        let mut book = synced_book();
+        assert!(!book.apply_diff(102, 105, &[(10.2, 4.)], &[]));
+        assert!(!book.is_synced());
+        assert_eq!(book.best_bid(), Some((10., 1.)));
+    }
+
+    #[test]
+    fn sequence_gap_requires_a_resync() {
+        let mut book = synced_book();
+        assert!(book.apply_diff(101, 105, &[], &[]));
+        assert!(!book.apply_diff(107, 110, &[(10.2, 4.)], &[]));
+        assert!(!book.is_synced());
+
+        // the following diffs are refused until a new snapshot
+        assert!(!book.apply_diff(111, 112, &[], &[]));
+        book.apply_snapshot(120, &[(9., 1.)], &[(9.5, 1.)]);
+        assert!(book.is_synced());
+        assert!(book.apply_diff(119, 121, &[], &[]));
+        assert_eq!(book.best_bid(), Some((9., 1.)));
+    }
+
+    #[test]
+    fn fill_price_walks_the_levels() {
+        let book = synced_book();
+        assert_eq!(book.fill_price(BookSide::Bid, 0.), Some(10.));
+        assert_eq!(book.fill_price(BookSide::Bid, 2.), Some(9.75));
+        assert_eq!(book.fill_price(BookSide::Ask, 3.), Some(32.5 / 3.));
+        assert_eq!(book.fill_price(BookSide::Ask, 7.), None);
+        assert_eq!(book.vwap_mid(1.), Some(10.25));
+    }
+}
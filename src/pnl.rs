@@ -0,0 +1,436 @@
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
+use vega_protobufs::vega::{Fee, Side as VegaSide, Trade};
+
+use crate::{
+    decimals::{self, Decimals},
+    ref_price::RefPrice,
+    vega_store::VegaStore,
+};
+
+// fills are read from the store, the spread capture uses the
+// reference price at the time they are accounted for
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+// trade IDs remembered to ignore the ones streamed twice
+const SEEN_TRADES: usize = 10000;
+
+/// Average cost accounting of our trades on the market, in human units
+pub struct Pnl {
+    pubkey: String,
+    decimals: Decimals,
+    rp: Arc<Mutex<RefPrice>>,
+    position: Decimal,
+    average_entry_price: Decimal,
+    realized: Decimal,
+    fees_paid: Decimal,
+    maker_rebates: Decimal,
+    // edge of each fill against the reference mid at the time we saw it
+    spread_capture: Decimal,
+    trades: u64,
+    volume: Decimal,
+    seen: HashSet<String>,
+    seen_order: VecDeque<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PnlReport {
+    pub position: Decimal,
+    // position reported by the vega positions stream
+    pub store_position: Decimal,
+    pub average_entry_price: Decimal,
+    pub realized: Decimal,
+    pub unrealized_mark: Decimal,
+    pub unrealized_reference: Decimal,
+    pub fees_paid: Decimal,
+    pub maker_rebates: Decimal,
+    pub spread_capture: Decimal,
+    // realized + unrealized against the mark price - fees + rebates
+    pub total: Decimal,
+    pub trades: u64,
+    pub volume: Decimal,
+}
+
+impl Pnl {
+    /// Starts from the current position, its history is not known
+    pub fn new(
+        pubkey: &str,
+        store: &VegaStore,
+        rp: Arc<Mutex<RefPrice>>,
+    ) -> Result<Pnl, decimals::Error> {
+        let market = store.get_market();
+        let asset = store.get_settlement_asset();
+        let decimals = Decimals::new(&market, &asset);
+
+        let (position, average_entry_price) = match store.get_position() {
+            Some(p) => (
+                decimals.from_market_position_precision(p.open_volume)?,
+                decimals.from_market_price_precision(&p.average_entry_price)?,
+            ),
+            None => (Decimal::ZERO, Decimal::ZERO),
+        };
+
+        return Ok(Pnl::with_position(
+            pubkey,
+            decimals,
+            rp,
+            position,
+            average_entry_price,
+        ));
+    }
+
+    fn with_position(
+        pubkey: &str,
+        decimals: Decimals,
+        rp: Arc<Mutex<RefPrice>>,
+        position: Decimal,
+        average_entry_price: Decimal,
+    ) -> Pnl {
+        return Pnl {
+            pubkey: pubkey.to_string(),
+            decimals,
+            rp,
+            position,
+            average_entry_price,
+            realized: Decimal::ZERO,
+            fees_paid: Decimal::ZERO,
+            maker_rebates: Decimal::ZERO,
+            spread_capture: Decimal::ZERO,
+            trades: 0,
+            volume: Decimal::ZERO,
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+        };
+    }
+
+    /// Accounts for the fills of the store not seen yet
+    pub fn sync(&mut self, store: &VegaStore) {
+        self.on_trades(&store.get_fills());
+    }
+
+    fn on_trades(&mut self, trades: &[Trade]) {
+        for t in trades.iter() {
+            if let Err(e) = self.on_trade(t) {
+                warn!("could not account for trade {}: {}", t.id, e);
+            }
+        }
+    }
+
+    fn on_trade(&mut self, t: &Trade) -> Result<(), decimals::Error> {
+        let (bought, own_fee, other_fee) = if t.buyer == self.pubkey && t.seller != self.pubkey {
+            (true, &t.buyer_fee, &t.seller_fee)
+        } else if t.seller == self.pubkey && t.buyer != self.pubkey {
+            (false, &t.seller_fee, &t.buyer_fee)
+        } else {
+            // not ours, or a self trade which does not change anything
+            return Ok(());
+        };
+        if !self.remember(&t.id) {
+            return Ok(());
+        }
+
+        let price = self.decimals.from_market_price_precision(&t.price)?;
+        let size = self
+            .decimals
+            .from_market_position_precision(t.size as i64)?;
+        let aggressor = match VegaSide::from_i32(t.aggressor) {
+            Some(VegaSide::Buy) => bought,
+            Some(VegaSide::Sell) => !bought,
+            _ => false,
+        };
+
+        // the aggressor pays the fees, the passive side receives the maker fee
+        self.fees_paid += self.total_fee(own_fee)?;
+        if !aggressor {
+            if let Some(fee) = other_fee {
+                self.maker_rebates += self.decimals.from_asset_precision(&fee.maker_fee)?;
+            }
+        }
+
+        let (bid, ask) = self.rp.lock().unwrap().get();
+        let mid = decimals::from_f64((bid + ask) / 2.)?;
+        self.spread_capture += match bought {
+            true => (mid - price) * size,
+            false => (price - mid) * size,
+        };
+
+        let signed = if bought { size } else { -size };
+        self.apply(signed, price);
+        self.trades += 1;
+        self.volume += size * price;
+
+        info!(
+            "trade {}: {} {} @ {}, position({}), entryPrice({}), realized({})",
+            t.id,
+            if bought { "bought" } else { "sold" },
+            size,
+            price,
+            self.position,
+            self.average_entry_price,
+            self.realized
+        );
+        return Ok(());
+    }
+
+    // average cost accounting of a signed fill
+    fn apply(&mut self, size: Decimal, price: Decimal) {
+        let same_side =
+            self.position.is_zero() || self.position.is_sign_positive() == size.is_sign_positive();
+        if same_side {
+            let total = self.position.abs() + size.abs();
+            self.average_entry_price =
+                (self.average_entry_price * self.position.abs() + price * size.abs()) / total;
+            self.position += size;
+            return;
+        }
+
+        let closed = size.abs().min(self.position.abs());
+        let direction = if self.position.is_sign_positive() {
+            Decimal::ONE
+        } else {
+            -Decimal::ONE
+        };
+        self.realized += closed * (price - self.average_entry_price) * direction;
+
+        let before = self.position;
+        self.position += size;
+        if self.position.is_zero() {
+            self.average_entry_price = Decimal::ZERO;
+        } else if self.position.is_sign_positive() != before.is_sign_positive() {
+            // flipped, the remainder was opened at the fill price
+            self.average_entry_price = price;
+        }
+    }
+
+    fn total_fee(&self, fee: &Option<Fee>) -> Result<Decimal, decimals::Error> {
+        return match fee {
+            Some(f) => Ok(self.decimals.from_asset_precision(&f.maker_fee)?
+                + self.decimals.from_asset_precision(&f.infrastructure_fee)?
+                + self.decimals.from_asset_precision(&f.liquidity_fee)?),
+            None => Ok(Decimal::ZERO),
+        };
+    }
+
+    // returns false if the trade was already accounted for
+    fn remember(&mut self, id: &str) -> bool {
+        if !self.seen.insert(id.to_string()) {
+            return false;
+        }
+        self.seen_order.push_back(id.to_string());
+        if self.seen_order.len() > SEEN_TRADES {
+            if let Some(old) = self.seen_order.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+        return true;
+    }
+
+    // of the open position if it was closed at the given price
+    fn unrealized(&self, price: Decimal) -> Decimal {
+        return (price - self.average_entry_price) * self.position;
+    }
+
+    pub fn report(&mut self, store: &VegaStore) -> Result<PnlReport, decimals::Error> {
+        self.sync(store);
+
+        let md = store.get_market_data();
+        let mark = match md.mark_price.as_str() {
+            "" => Decimal::ZERO,
+            p => self.decimals.from_market_price_precision(p)?,
+        };
+        let (bid, ask) = self.rp.lock().unwrap().get();
+        let reference = decimals::from_f64((bid + ask) / 2.)?;
+        let store_position = match store.get_position() {
+            Some(p) => self
+                .decimals
+                .from_market_position_precision(p.open_volume)?,
+            None => Decimal::ZERO,
+        };
+
+        let unrealized_mark = self.unrealized(mark);
+        let unrealized_reference = self.unrealized(reference);
+        return Ok(PnlReport {
+            position: self.position,
+            store_position,
+            average_entry_price: self.average_entry_price,
+            realized: self.realized,
+            unrealized_mark,
+            unrealized_reference,
+            fees_paid: self.fees_paid,
+            maker_rebates: self.maker_rebates,
+            spread_capture: self.spread_capture,
+            total: self.realized + unrealized_mark - self.fees_paid + self.maker_rebates,
+            trades: self.trades,
+            volume: self.volume,
+        });
+    }
+}
+
+/// Accounts for the new fills of the store and logs the PnL periodically
+pub async fn start(pnl: Arc<Mutex<Pnl>>, store: Arc<Mutex<VegaStore>>) {
+    let mut sync = time::interval(SYNC_INTERVAL);
+    let mut log = time::interval(LOG_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = sync.tick() => {
+                pnl.lock().unwrap().sync(&store.lock().unwrap());
+                continue;
+            }
+            _ = log.tick() => {}
+        }
+        let report = pnl.lock().unwrap().report(&store.lock().unwrap());
+        match report {
+            Ok(r) => {
+                info!("pnl: {:?}", r);
+                if r.position != r.store_position {
+                    warn!(
+                        "pnl position {} differs from the vega position {}, some trades were missed",
+                        r.position, r.store_position
+                    );
+                }
+            }
+            Err(e) => warn!("could not compute the pnl: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::decimals::testing::{dec, without_decimals};
+
+    // reference mid at 100
+    fn pnl() -> Pnl {
+        let (_, _, decimals) = without_decimals();
+        let mut rp = RefPrice::new();
+        rp.set(99., 1., 101., 1., None);
+        return Pnl::with_position(
+            "us",
+            decimals,
+            Arc::new(Mutex::new(rp)),
+            Decimal::ZERO,
+            Decimal::ZERO,
+        );
+    }
+
+    fn fee(maker: &str, infrastructure: &str, liquidity: &str) -> Option<Fee> {
+        return Some(Fee {
+            maker_fee: maker.to_string(),
+            infrastructure_fee: infrastructure.to_string(),
+            liquidity_fee: liquidity.to_string(),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn long_position_average_cost() {
+        let mut p = pnl();
+        p.apply(dec("2"), dec("100"));
+        p.apply(dec("2"), dec("110"));
+        assert_eq!(p.position, dec("4"));
+        assert_eq!(p.average_entry_price, dec("105"));
+        assert_eq!(p.unrealized(dec("115")), dec("40"));
+
+        // a partial close keeps the entry price
+        p.apply(dec("-3"), dec("120"));
+        assert_eq!(p.position, dec("1"));
+        assert_eq!(p.average_entry_price, dec("105"));
+        assert_eq!(p.realized, dec("45"));
+
+        // flipping opens the remainder at the fill price
+        p.apply(dec("-2"), dec("100"));
+        assert_eq!(p.position, dec("-1"));
+        assert_eq!(p.average_entry_price, dec("100"));
+        assert_eq!(p.realized, dec("40"));
+        assert_eq!(p.unrealized(dec("90")), dec("10"));
+    }
+
+    #[test]
+    fn short_position_average_cost() {
+        let mut p = pnl();
+        p.apply(dec("-2"), dec("100"));
+        p.apply(dec("1"), dec("90"));
+        assert_eq!(p.position, dec("-1"));
+        assert_eq!(p.average_entry_price, dec("100"));
+        assert_eq!(p.realized, dec("10"));
+        assert_eq!(p.unrealized(dec("110")), dec("-10"));
+
+        p.apply(dec("1"), dec("95"));
+        assert!(p.position.is_zero());
+        assert!(p.average_entry_price.is_zero());
+        assert_eq!(p.realized, dec("15"));
+        assert!(p.unrealized(dec("200")).is_zero());
+    }
+
+    #[test]
+    fn aggressor_pays_the_fees() {
+        let mut p = pnl();
+        let trade = Trade {
+            id: "t1".to_string(),
+            price: "101".to_string(),
+            size: 2,
+            buyer: "us".to_string(),
+            seller: "them".to_string(),
+            aggressor: VegaSide::Buy as i32,
+            buyer_fee: fee("1", "2", "3"),
+            seller_fee: None,
+            ..Default::default()
+        };
+
+        p.on_trades(&[trade.clone()]);
+        assert_eq!(p.position, dec("2"));
+        assert_eq!(p.fees_paid, dec("6"));
+        assert!(p.maker_rebates.is_zero());
+        assert_eq!(p.spread_capture, dec("-2"));
+
+        // streamed again
+        p.on_trades(&[trade]);
+        assert_eq!(p.position, dec("2"));
+        assert_eq!(p.trades, 1);
+    }
+
+    #[test]
+    fn passive_side_receives_the_maker_fee() {
+        let mut p = pnl();
+        let trade = Trade {
+            id: "t1".to_string(),
+            price: "101".to_string(),
+            size: 2,
+            buyer: "them".to_string(),
+            seller: "us".to_string(),
+            aggressor: VegaSide::Buy as i32,
+            buyer_fee: fee("1", "2", "3"),
+            seller_fee: None,
+            ..Default::default()
+        };
+
+        p.on_trades(&[trade]);
+        assert_eq!(p.position, dec("-2"));
+        assert!(p.fees_paid.is_zero());
+        assert_eq!(p.maker_rebates, dec("1"));
+        assert_eq!(p.spread_capture, dec("2"));
+    }
+
+    #[test]
+    fn other_trades_are_ignored() {
+        let mut p = pnl();
+        let trade = Trade {
+            id: "t1".to_string(),
+            price: "100".to_string(),
+            size: 1,
+            buyer: "us".to_string(),
+            seller: "us".to_string(),
+            ..Default::default()
+        };
+
+        p.on_trades(&[trade]);
+        assert!(p.position.is_zero());
+        assert_eq!(p.trades, 0);
+    }
+}
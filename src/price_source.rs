@@ -0,0 +1,379 @@
+use futures::future::BoxFuture;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use log::{info, warn};
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time;
+use tokio_tungstenite::{connect_async, tungstenite, tungstenite::Message};
+use url::Url;
+
+use crate::backoff::Backoff;
+use crate::ref_price::{FeedState, RefPrice};
+
+// venues ping at most every few minutes and the streams are pushed
+// at least every second on liquid markets, a silent socket for this
+// long is considered dead, the pings we send do not count
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// interval of the application level pings for the venues requiring them
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+pub enum Handled {
+    Subscribed,
+    Price,
+    Ignored,
+}
+
+/// A websocket feed of a venue producing a reference price
+pub trait PriceSource: Send + 'static {
+    fn name(&self) -> String;
+
+    fn url(&self) -> String;
+
+    // messages sent once connected to subscribe to the venue streams
+    fn subscriptions(&self) -> Result<Vec<String>, Error>;
+
+    fn handle_message(&mut self, text: &str, rp: &Mutex<RefPrice>) -> Result<Handled, Error>;
+
+    // called once the subscription is confirmed, before the feed
+    // is marked as connected
+    fn on_subscribed<'a>(
+        &'a mut self,
+        _rp: &'a Mutex<RefPrice>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        return Box::pin(async { Ok(()) });
+    }
+
+    // application level ping message, for venues requiring one
+    fn heartbeat(&self) -> Option<String> {
+        return None;
+    }
+}
+
+// prices must be finite and positive and volumes finite and not negative,
+// anything else from a venue is an invalid message
+pub fn parse_price(s: &str) -> Result<f64, Error> {
+    let price = s.parse::<f64>()?;
+    if !price.is_finite() || price <= 0. {
+        return Err(Error::InvalidMessage);
+    }
+    return Ok(price);
+}
+
+pub fn parse_volume(s: &str) -> Result<f64, Error> {
+    let volume = s.parse::<f64>()?;
+    if !volume.is_finite() || volume < 0. {
+        return Err(Error::InvalidMessage);
+    }
+    return Ok(volume);
+}
+
+// [price, volume] levels of the venues streaming depth
+pub fn parse_levels(levels: &[(String, String)]) -> Result<Vec<(f64, f64)>, Error> {
+    let mut out = vec![];
+    for (p, q) in levels.iter() {
+        out.push((parse_price(p)?, parse_volume(q)?));
+    }
+    return Ok(out);
+}
+
+pub async fn start<S: PriceSource>(
+    mut source: S,
+    rp: Arc<Mutex<RefPrice>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let name = source.name();
+    let mut backoff = Backoff::reconnect();
+    loop {
+        match run(&mut source, rp.clone(), &mut backoff, &mut shutdown).await {
+            Ok(()) => {
+                info!("{} feed stopped", name);
+                rp.lock().unwrap().set_state(FeedState::Disconnected);
+                return;
+            }
+            Err(e) => warn!("{} feed error: {}", name, e),
+        }
+
+        rp.lock().unwrap().set_state(FeedState::Disconnected);
+        let delay = backoff.next();
+        info!("reconnecting to {} in {:?}", name, delay);
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = shutdown.changed() => {
+                info!("{} feed stopped", name);
+                return;
+            }
+        }
+        rp.lock().unwrap().set_state(FeedState::Connecting);
+    }
+}
+
+// run a single websocket session, returns Ok only if the
+// feed was asked to stop
+async fn run<S: PriceSource>(
+    source: &mut S,
+    rp: Arc<Mutex<RefPrice>>,
+    backoff: &mut Backoff,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<(), Error> {
+    let name = source.name();
+    let url = source.url().parse::<Url>()?;
+    info!("opening websocket with {} at: {}", name, url);
+    let (mut socket, _) = time::timeout(READ_TIMEOUT, connect_async(url)).await??;
+    info!("connected to {} successfully", name);
+
+    for request in source.subscriptions()?.into_iter() {
+        socket.send(Message::Text(request)).await?;
+    }
+
+    return read(
+        &mut socket,
+        source,
+        rp,
+        backoff,
+        shutdown,
+        HEARTBEAT_INTERVAL,
+        READ_TIMEOUT,
+    )
+    .await;
+}
+
+// handle the messages of a connected socket until it fails, goes
+// silent for longer than the read timeout, or the feed is stopped
+async fn read<W, S>(
+    socket: &mut W,
+    source: &mut S,
+    rp: Arc<Mutex<RefPrice>>,
+    backoff: &mut Backoff,
+    shutdown: &mut watch::Receiver<bool>,
+    heartbeat_interval: Duration,
+    read_timeout: Duration,
+) -> Result<(), Error>
+where
+    W: Stream<Item = Result<Message, tungstenite::Error>>
+        + Sink<Message, Error = tungstenite::Error>
+        + Unpin,
+    S: PriceSource,
+{
+    let name = source.name();
+    let pings = source.heartbeat().is_some();
+    let mut heartbeat = time::interval(heartbeat_interval);
+    // only reset by the frames received
+    let mut last_read = time::Instant::now();
+    loop {
+        let msg = tokio::select! {
+            msg = socket.next() => match msg {
+                Some(msg) => msg?,
+                None => return Err(Error::Closed),
+            },
+            _ = time::sleep_until(last_read + read_timeout) => return Err(Error::Timeout),
+            _ = heartbeat.tick(), if pings => {
+                if let Some(ping) = source.heartbeat() {
+                    socket.send(Message::Text(ping)).await?;
+                }
+                continue;
+            }
+            _ = shutdown.changed() => {
+                socket.close().await?;
+                return Ok(());
+            }
+        };
+        last_read = time::Instant::now();
+
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Ping(payload) => {
+                socket.send(Message::Pong(payload)).await?;
+                continue;
+            }
+            Message::Close(_) => return Err(Error::Closed),
+            _ => continue,
+        };
+
+        match source.handle_message(&text, &rp) {
+            Ok(Handled::Subscribed) => {
+                source.on_subscribed(&rp).await?;
+                rp.lock().unwrap().set_state(FeedState::Connected);
+                backoff.reset();
+            }
+            Ok(Handled::Price) | Ok(Handled::Ignored) => {}
+            // the book cannot be trusted anymore, resync from scratch
+            Err(Error::OutOfSync) => return Err(Error::OutOfSync),
+            Err(e) => warn!("could not handle {} message ({}): {}", name, e, text),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WSError,
+    Timeout,
+    Closed,
+    InvalidMessage,
+    EmptyBook,
+    OutOfSync,
+    RestError,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "price source error: {}", self.desc())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(_: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WSError
+    }
+}
+
+impl From<time::error::Elapsed> for Error {
+    fn from(_: time::error::Elapsed) -> Self {
+        Error::Timeout
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(_: url::ParseError) -> Self {
+        Error::WSError
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(_: serde_json::Error) -> Self {
+        Error::WSError
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(_: hyper::Error) -> Self {
+        Error::RestError
+    }
+}
+
+impl From<hyper::http::uri::InvalidUri> for Error {
+    fn from(_: hyper::http::uri::InvalidUri) -> Self {
+        Error::RestError
+    }
+}
+
+impl From<std::num::ParseFloatError> for Error {
+    fn from(_: std::num::ParseFloatError) -> Self {
+        Error::InvalidMessage
+    }
+}
+
+impl StdError for Error {}
+
+impl Error {
+    pub fn desc(&self) -> String {
+        use Error::*;
+        match self {
+            WSError => format!("websocket error"),
+            Timeout => format!("websocket read timeout"),
+            Closed => format!("websocket closed by remote"),
+            InvalidMessage => format!("invalid message"),
+            EmptyBook => format!("empty order book"),
+            OutOfSync => format!("order book out of sync"),
+            RestError => format!("REST API error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{tungstenite::protocol::Role, WebSocketStream};
+
+    struct Silent {
+        ping: Option<String>,
+    }
+
+    impl PriceSource for Silent {
+        fn name(&self) -> String {
+            return "silent".to_string();
+        }
+
+        fn url(&self) -> String {
+            return "".to_string();
+        }
+
+        fn subscriptions(&self) -> Result<Vec<String>, Error> {
+            return Ok(vec![]);
+        }
+
+        fn handle_message(&mut self, _: &str, _: &Mutex<RefPrice>) -> Result<Handled, Error> {
+            return Ok(Handled::Ignored);
+        }
+
+        fn heartbeat(&self) -> Option<String> {
+            return self.ping.clone();
+        }
+    }
+
+    async fn socket_pair() -> (WebSocketStream<TcpStream>, WebSocketStream<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        return (
+            WebSocketStream::from_raw_socket(client, Role::Client, None).await,
+            WebSocketStream::from_raw_socket(server, Role::Server, None).await,
+        );
+    }
+
+    // read with pings sent more often than the read timeout
+    async fn read_silent(
+        client: &mut WebSocketStream<TcpStream>,
+        ping: Option<String>,
+    ) -> Result<Result<(), Error>, time::error::Elapsed> {
+        let rp = Arc::new(Mutex::new(RefPrice::new()));
+        let (_stop, mut shutdown) = watch::channel(false);
+        let mut backoff = Backoff::reconnect();
+        return time::timeout(
+            Duration::from_secs(5),
+            read(
+                client,
+                &mut Silent { ping },
+                rp,
+                &mut backoff,
+                &mut shutdown,
+                Duration::from_millis(20),
+                Duration::from_millis(200),
+            ),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn silent_socket_times_out() {
+        for ping in [None, Some("ping".to_string())] {
+            let (mut client, _server) = socket_pair().await;
+            let res = read_silent(&mut client, ping).await;
+            assert!(matches!(res, Ok(Err(Error::Timeout))));
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_reset_the_read_timeout() {
+        let (mut client, mut server) = socket_pair().await;
+        let start = time::Instant::now();
+        // pushes for longer than the read timeout, then goes silent
+        let pusher = tokio::spawn(async move {
+            for _ in 0..10 {
+                server.send(Message::Text("{}".to_string())).await.unwrap();
+                time::sleep(Duration::from_millis(50)).await;
+            }
+            return server;
+        });
+        let res = read_silent(&mut client, Some("ping".to_string())).await;
+        assert!(matches!(res, Ok(Err(Error::Timeout))));
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        drop(pusher.await.unwrap());
+    }
+}
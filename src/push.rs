@@ -0,0 +1,106 @@
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, WebSocketStream};
+
+use crate::events::{self, Event};
+use crate::ref_price::RefPrice;
+use crate::views::{AccountView, OrderView, PositionView, RefPriceView, TradeView};
+
+// slow websocket clients skip the oldest messages past this
+const CAPACITY: usize = 1024;
+
+/// Changes of the bot state pushed to the websocket clients
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Message {
+    ReferencePrice(RefPriceView),
+    Order(OrderView),
+    Fill(TradeView),
+    Position(PositionView),
+    Account(AccountView),
+    Decision(Decision),
+}
+
+/// What a strategy run decided to do with the quotes
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Decision {
+    // a batch was sent to move the quotes
+    Quote {
+        cancellations: usize,
+        amendments: usize,
+        submissions: usize,
+    },
+    // the live quotes are within tolerance
+    Hold,
+    Pull {
+        reason: String,
+    },
+    Kill {
+        reason: String,
+    },
+}
+
+pub type Sender = broadcast::Sender<Message>;
+pub type Receiver = broadcast::Receiver<Message>;
+
+pub fn channel() -> Sender {
+    let (tx, _) = broadcast::channel(CAPACITY);
+    return tx;
+}
+
+/// Pushes the messages to a websocket client until it goes away,
+/// the reference price is taken from the events waking the strategy
+pub async fn serve<S>(
+    mut ws: WebSocketStream<S>,
+    mut messages: Receiver,
+    mut events: events::Receiver,
+    rp: Arc<Mutex<RefPrice>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!("websocket client connected");
+    loop {
+        let msg = tokio::select! {
+            msg = messages.recv() => match msg {
+                Ok(msg) => msg,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("websocket client missed {} messages", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            ev = events.recv() => match ev {
+                Ok(Event::ReferencePrice { .. }) => {
+                    let view = RefPriceView::from(&*rp.lock().unwrap());
+                    Message::ReferencePrice(view)
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            // the client is not expected to send anything but
+            // reading lets the pings be answered
+            incoming = ws.next() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        let text = match serde_json::to_string(&msg) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("could not serialize {:?}: {}", msg, e);
+                continue;
+            }
+        };
+        if let Err(e) = ws.send(WsMessage::Text(text)).await {
+            warn!("could not push to the websocket client: {}", e);
+            break;
+        }
+    }
+    info!("websocket client disconnected");
+}
@@ -0,0 +1,320 @@
+use vega_protobufs::vega::Order;
+use vega_wallet_client::commands::{
+    BatchMarketInstructions, OrderAmendment, OrderCancellation, OrderSubmission, PeggedReference,
+    Side, TimeInForce,
+};
+
+use crate::strategy_config::StrategyConfig;
+
+/// Computes the instructions moving the live orders to the desired quotes.
+/// Live orders within the price and size tolerances of a desired quote are
+/// left alone, the others are amended in place when possible so a size
+/// decrease keeps its queue priority, extra live orders are cancelled and
+/// missing quotes are submitted.
+pub fn reconcile(
+    market_id: &str,
+    desired: Vec<OrderSubmission>,
+    live: &[Order],
+    cfg: &StrategyConfig,
+) -> BatchMarketInstructions {
+    let mut batch = BatchMarketInstructions {
+        cancellations: vec![],
+        amendments: vec![],
+        submissions: vec![],
+    };
+
+    for side in [Side::Buy, Side::Sell] {
+        let want = desired
+            .iter()
+            .filter(|o| o.side == side && o.size > 0)
+            .cloned()
+            .collect::<Vec<OrderSubmission>>();
+        let have = live
+            .iter()
+            .filter(|o| o.market_id == market_id && order_side(o) == Some(side))
+            .collect::<Vec<&Order>>();
+        reconcile_side(market_id, side, want, have, cfg, &mut batch);
+    }
+
+    return batch;
+}
+
+/// A batch cancelling all our orders on the market
+pub fn cancel_all(market_id: &str) -> BatchMarketInstructions {
+    return BatchMarketInstructions {
+        cancellations: vec![OrderCancellation {
+            market_id: market_id.to_string(),
+            order_id: "".to_string(),
+        }],
+        amendments: vec![],
+        submissions: vec![],
+    };
+}
+
+pub fn is_empty(batch: &BatchMarketInstructions) -> bool {
+    return batch.cancellations.is_empty()
+        && batch.amendments.is_empty()
+        && batch.submissions.is_empty();
+}
+
+fn reconcile_side(
+    market_id: &str,
+    side: Side,
+    mut want: Vec<OrderSubmission>,
+    mut have: Vec<&Order>,
+    cfg: &StrategyConfig,
+    batch: &mut BatchMarketInstructions,
+) {
+    // first keep every live order already matching a desired quote
+    want.retain(|quote| {
+        let pos = have.iter().position(|o| {
+            price_within(quote, o, cfg.price_tolerance_bps)
+                && size_within(quote, o, cfg.size_tolerance)
+        });
+        match pos {
+            Some(i) => {
+                have.remove(i);
+                false
+            }
+            None => true,
+        }
+    });
+
+    // then pair the remaining ones by distance to the touch,
+    // so each order moves as little as possible
+    want.sort_by(|a, b| by_touch(side, parse(&a.price), parse(&b.price)));
+    have.sort_by(|a, b| by_touch(side, parse(&a.price), parse(&b.price)));
+
+    let mut have = have.into_iter();
+    for quote in want.into_iter() {
+        match have.next() {
+            Some(o) => {
+                if let Some(a) = amendment(market_id, &quote, o, cfg) {
+                    batch.amendments.push(a);
+                }
+            }
+            None => batch.submissions.push(quote),
+        }
+    }
+
+    for o in have {
+        batch.cancellations.push(OrderCancellation {
+            market_id: market_id.to_string(),
+            order_id: o.id.clone(),
+        });
+    }
+}
+
+// None if the order is already within the tolerances of the quote,
+// vega rejects amendments which do not change anything
+fn amendment(
+    market_id: &str,
+    quote: &OrderSubmission,
+    o: &Order,
+    cfg: &StrategyConfig,
+) -> Option<OrderAmendment> {
+    let price = match price_within(quote, o, cfg.price_tolerance_bps) {
+        true => None,
+        false => Some(quote.price.clone()),
+    };
+    let size_delta = match size_within(quote, o, cfg.size_tolerance) {
+        true => 0,
+        false => quote.size as i64 - o.remaining as i64,
+    };
+
+    if price.is_none() && size_delta == 0 {
+        return None;
+    }
+
+    return Some(OrderAmendment {
+        order_id: o.id.clone(),
+        market_id: market_id.to_string(),
+        price,
+        size_delta,
+        expires_at: None,
+        time_in_force: TimeInForce::Unspecified,
+        pegged_offset: "".to_string(),
+        pegged_reference: PeggedReference::Unspecified,
+    });
+}
+
+fn price_within(quote: &OrderSubmission, o: &Order, tolerance_bps: f64) -> bool {
+    let want = parse(&quote.price);
+    let have = parse(&o.price);
+    if want == 0. {
+        return have == 0.;
+    }
+    return ((have - want) / want).abs() * 10000. <= tolerance_bps;
+}
+
+fn size_within(quote: &OrderSubmission, o: &Order, tolerance: f64) -> bool {
+    let want = quote.size as f64;
+    let have = o.remaining as f64;
+    return ((have - want) / want).abs() <= tolerance;
+}
+
+// closest to the touch first, highest bids and lowest asks
+fn by_touch(side: Side, a: f64, b: f64) -> std::cmp::Ordering {
+    let ord = a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal);
+    return match side {
+        Side::Buy => ord.reverse(),
+        _ => ord,
+    };
+}
+
+fn order_side(o: &Order) -> Option<Side> {
+    use vega_protobufs::vega::Side as VegaSide;
+    return match VegaSide::from_i32(o.side) {
+        Some(VegaSide::Buy) => Some(Side::Buy),
+        Some(VegaSide::Sell) => Some(Side::Sell),
+        _ => None,
+    };
+}
+
+fn parse(price: &str) -> f64 {
+    return price.parse::<f64>().unwrap_or(0.);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vega_protobufs::vega::Side as VegaSide;
+    use vega_wallet_client::commands::OrderType;
+
+    const MARKET: &str = "market";
+
+    fn quote(side: Side, price: &str, size: u64) -> OrderSubmission {
+        return OrderSubmission {
+            market_id: MARKET.to_string(),
+            price: price.to_string(),
+            size,
+            side,
+            time_in_force: TimeInForce::Gtc,
+            expires_at: 0,
+            r#type: OrderType::Limit,
+            reference: "".to_string(),
+            pegged_order: None,
+        };
+    }
+
+    fn live(id: &str, side: VegaSide, price: &str, remaining: u64) -> Order {
+        return Order {
+            id: id.to_string(),
+            market_id: MARKET.to_string(),
+            side: side as i32,
+            price: price.to_string(),
+            size: remaining,
+            remaining,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn exact_match_is_left_alone() {
+        let cfg = StrategyConfig::default();
+        let desired = vec![
+            quote(Side::Buy, "100000", 100),
+            quote(Side::Sell, "101000", 100),
+        ];
+        let orders = vec![
+            live("b", VegaSide::Buy, "100000", 100),
+            live("s", VegaSide::Sell, "101000", 100),
+        ];
+
+        let batch = reconcile(MARKET, desired, &orders, &cfg);
+        assert!(is_empty(&batch));
+    }
+
+    #[test]
+    fn within_tolerance_is_left_alone() {
+        // 5bps and 10% by default
+        let cfg = StrategyConfig::default();
+        let desired = vec![quote(Side::Buy, "100000", 100)];
+        let orders = vec![live("b", VegaSide::Buy, "100040", 95)];
+
+        let batch = reconcile(MARKET, desired, &orders, &cfg);
+        assert!(is_empty(&batch));
+    }
+
+    #[test]
+    fn no_amendment_within_tolerance() {
+        let cfg = StrategyConfig::default();
+        let q = quote(Side::Buy, "100000", 100);
+        let o = live("b", VegaSide::Buy, "100040", 95);
+
+        assert!(amendment(MARKET, &q, &o, &cfg).is_none());
+    }
+
+    #[test]
+    fn price_move_is_amended() {
+        let cfg = StrategyConfig::default();
+        let desired = vec![quote(Side::Sell, "101000", 100)];
+        let orders = vec![live("s", VegaSide::Sell, "102000", 100)];
+
+        let batch = reconcile(MARKET, desired, &orders, &cfg);
+        assert!(batch.cancellations.is_empty());
+        assert!(batch.submissions.is_empty());
+        assert_eq!(batch.amendments.len(), 1);
+        let a = &batch.amendments[0];
+        assert_eq!(a.order_id, "s");
+        assert_eq!(a.price, Some("101000".to_string()));
+        assert_eq!(a.size_delta, 0);
+    }
+
+    #[test]
+    fn size_change_is_amended() {
+        let cfg = StrategyConfig::default();
+        let desired = vec![quote(Side::Buy, "100000", 100)];
+        let orders = vec![live("b", VegaSide::Buy, "100000", 150)];
+
+        let batch = reconcile(MARKET, desired, &orders, &cfg);
+        assert_eq!(batch.amendments.len(), 1);
+        let a = &batch.amendments[0];
+        assert_eq!(a.order_id, "b");
+        assert_eq!(a.price, None);
+        assert_eq!(a.size_delta, -50);
+    }
+
+    #[test]
+    fn extra_orders_are_cancelled() {
+        let cfg = StrategyConfig::default();
+        let desired = vec![quote(Side::Buy, "100000", 100)];
+        let orders = vec![
+            live("b1", VegaSide::Buy, "100000", 100),
+            live("b2", VegaSide::Buy, "99000", 100),
+        ];
+
+        let batch = reconcile(MARKET, desired, &orders, &cfg);
+        assert!(batch.amendments.is_empty());
+        assert!(batch.submissions.is_empty());
+        assert_eq!(batch.cancellations.len(), 1);
+        assert_eq!(batch.cancellations[0].order_id, "b2");
+    }
+
+    #[test]
+    fn missing_quotes_are_submitted() {
+        let cfg = StrategyConfig::default();
+        let desired = vec![
+            quote(Side::Buy, "100000", 100),
+            quote(Side::Sell, "101000", 100),
+        ];
+        let orders = vec![live("b", VegaSide::Buy, "100000", 100)];
+
+        let batch = reconcile(MARKET, desired, &orders, &cfg);
+        assert!(batch.amendments.is_empty());
+        assert!(batch.cancellations.is_empty());
+        assert_eq!(batch.submissions.len(), 1);
+        assert!(batch.submissions[0].side == Side::Sell);
+        assert_eq!(batch.submissions[0].price, "101000");
+    }
+
+    #[test]
+    fn orders_of_other_markets_are_ignored() {
+        let cfg = StrategyConfig::default();
+        let mut other = live("o", VegaSide::Buy, "100000", 100);
+        other.market_id = "other".to_string();
+
+        let batch = reconcile(MARKET, vec![], &[other], &cfg);
+        assert!(is_empty(&batch));
+    }
+}
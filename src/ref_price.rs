@@ -0,0 +1,213 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::order_book::{BookSide, OrderBook};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// State of a feed or a stream and how often it was lost
+#[derive(Debug, Clone)]
+pub struct ConnectionStatus {
+    pub state: FeedState,
+    // when the connection was last lost, None if it never was
+    pub disconnected_at: Option<Instant>,
+    pub reconnects: u64,
+}
+
+impl ConnectionStatus {
+    pub fn new() -> ConnectionStatus {
+        return ConnectionStatus {
+            state: FeedState::Connecting,
+            disconnected_at: None,
+            reconnects: 0,
+        };
+    }
+
+    pub fn set_state(&mut self, state: FeedState) {
+        match state {
+            FeedState::Disconnected if self.state != FeedState::Disconnected => {
+                self.disconnected_at = Some(Instant::now())
+            }
+            FeedState::Connected
+                if self.state != FeedState::Connected && self.disconnected_at.is_some() =>
+            {
+                self.reconnects += 1
+            }
+            _ => {}
+        }
+        self.state = state;
+    }
+}
+
+pub struct RefPrice {
+    bid_price: f64,
+    bid_volume: f64,
+    ask_price: f64,
+    ask_volume: f64,
+    // exchange event time in milliseconds since epoch
+    event_time: u64,
+    // None until the first price is received
+    received_at: Option<Instant>,
+    // how late the price already was when we received it
+    event_lag: Duration,
+    status: ConnectionStatus,
+    // only maintained by the sources streaming depth
+    book: OrderBook,
+}
+
+impl RefPrice {
+    pub fn new() -> RefPrice {
+        return RefPrice {
+            bid_price: 0.,
+            bid_volume: 0.,
+            ask_price: 0.,
+            ask_volume: 0.,
+            event_time: 0,
+            received_at: None,
+            event_lag: Duration::ZERO,
+            status: ConnectionStatus::new(),
+            book: OrderBook::new(),
+        };
+    }
+
+    pub fn set_state(&mut self, state: FeedState) {
+        self.status.set_state(state);
+    }
+
+    pub fn get_state(&self) -> FeedState {
+        return self.status.state;
+    }
+
+    pub fn get_disconnected_at(&self) -> Option<Instant> {
+        return self.status.disconnected_at;
+    }
+
+    pub fn get_reconnects(&self) -> u64 {
+        return self.status.reconnects;
+    }
+
+    // event_time is None for streams which do not carry it,
+    // in which case the local receive time is used
+    pub fn set(
+        &mut self,
+        bid_price: f64,
+        bid_volume: f64,
+        ask_price: f64,
+        ask_volume: f64,
+        event_time: Option<u64>,
+    ) {
+        self.bid_price = bid_price;
+        self.bid_volume = bid_volume;
+        self.ask_price = ask_price;
+        self.ask_volume = ask_volume;
+        self.received_at = Some(Instant::now());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.event_time = event_time.unwrap_or(now);
+        self.event_lag = Duration::from_millis(now.saturating_sub(self.event_time));
+    }
+
+    // the venue confirmed the last price is still current,
+    // nothing is done until a first price is received
+    pub fn refresh(&mut self) {
+        if self.received_at.is_some() {
+            self.set(
+                self.bid_price,
+                self.bid_volume,
+                self.ask_price,
+                self.ask_volume,
+                None,
+            );
+        }
+    }
+
+    pub fn get(&self) -> (f64, f64) {
+        return (self.bid_price, self.ask_price);
+    }
+
+    // bid and ask to convert through this pair as a cross rate leg,
+    // an inverted leg buys at the inverse of the bid and sells at
+    // the inverse of the ask
+    pub fn get_cross_rate(&self, invert: bool) -> (f64, f64) {
+        if invert {
+            return (1. / self.ask_price, 1. / self.bid_price);
+        }
+        return (self.bid_price, self.ask_price);
+    }
+
+    pub fn get_volumes(&self) -> (f64, f64) {
+        return (self.bid_volume, self.ask_volume);
+    }
+
+    pub fn get_book(&self) -> &OrderBook {
+        return &self.book;
+    }
+
+    pub fn get_book_mut(&mut self) -> &mut OrderBook {
+        return &mut self.book;
+    }
+
+    // bid and ask references for the given pricing, None if the
+    // book is not available or not deep enough for the volume
+    pub fn get_reference(&self, pricing: RefPricing, volume: f64) -> Option<(f64, f64)> {
+        return match pricing {
+            RefPricing::Top => Some(self.get()),
+            _ if !self.book.is_synced() => None,
+            RefPricing::VwapMid => self.book.vwap_mid(volume).map(|mid| (mid, mid)),
+            RefPricing::FillPrice => Some((
+                self.book.fill_price(BookSide::Bid, volume)?,
+                self.book.fill_price(BookSide::Ask, volume)?,
+            )),
+        };
+    }
+
+    pub fn get_event_time(&self) -> u64 {
+        return self.event_time;
+    }
+
+    // age of the price, counted from the exchange event time,
+    // None if no price was ever received
+    pub fn get_age(&self) -> Option<Duration> {
+        return self.received_at.map(|t| t.elapsed() + self.event_lag);
+    }
+
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        return match self.get_age() {
+            Some(age) => age > max_age,
+            None => true,
+        };
+    }
+}
+
+/// How the bid and ask references are derived from a venue feed
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RefPricing {
+    /// Best bid and ask
+    Top,
+    /// Mid of the volume weighted prices to fill the reference volume
+    VwapMid,
+    /// Prices to fill the reference volume on each side of the book
+    FillPrice,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverted_cross_rate_swaps_the_sides() {
+        let mut rp = RefPrice::new();
+        rp.set(2., 1., 4., 1., None);
+        assert_eq!(rp.get_cross_rate(false), (2., 4.));
+        // the inverse of the ask is the lower price
+        assert_eq!(rp.get_cross_rate(true), (0.25, 0.5));
+    }
+}
@@ -0,0 +1,410 @@
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use vega_wallet_client::commands::{BatchMarketInstructions, Side};
+
+use crate::{
+    decimals,
+    strategy::{Error, Quote, Snapshot},
+};
+
+/// Hard limits checked on every batch before it is sent,
+/// a limit set to 0 is disabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskConfig {
+    // absolute position we could reach if all the quotes of a side filled
+    pub max_position: f64,
+    // total price * size of the quotes of a side
+    pub max_notional_per_side: f64,
+    pub max_order_size: f64,
+    // live orders on the market once the batch is applied
+    pub max_live_orders: usize,
+    // maximum distance of a quote to the reference mid, as a fraction
+    pub price_collar: f64,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        return RiskConfig {
+            max_position: 0.,
+            max_notional_per_side: 0.,
+            max_order_size: 0.,
+            max_live_orders: 20,
+            price_collar: 0.05,
+        };
+    }
+}
+
+impl RiskConfig {
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.max_position < 0. {
+            errors.push("strategy.risk.max_position must not be negative".to_string());
+        }
+        if self.max_notional_per_side < 0. {
+            errors.push("strategy.risk.max_notional_per_side must not be negative".to_string());
+        }
+        if self.max_order_size < 0. {
+            errors.push("strategy.risk.max_order_size must not be negative".to_string());
+        }
+        if self.price_collar < 0. || self.price_collar >= 1. {
+            errors.push("strategy.risk.price_collar must be between 0 and 1".to_string());
+        }
+        return errors;
+    }
+}
+
+#[derive(clap::Args)]
+pub struct RiskArgs {
+    /// Maximum absolute position reachable if all the quotes of a side filled, 0 disables
+    #[arg(long, env = "VEGAMM_RISK_MAX_POSITION")]
+    risk_max_position: Option<f64>,
+    /// Maximum notional of the quotes of a side, 0 disables
+    #[arg(long, env = "VEGAMM_RISK_MAX_NOTIONAL_PER_SIDE")]
+    risk_max_notional_per_side: Option<f64>,
+    /// Maximum size of a single order, 0 disables
+    #[arg(long, env = "VEGAMM_RISK_MAX_ORDER_SIZE")]
+    risk_max_order_size: Option<f64>,
+    /// Maximum number of live orders on the market, 0 disables
+    #[arg(long, env = "VEGAMM_RISK_MAX_LIVE_ORDERS")]
+    risk_max_live_orders: Option<usize>,
+    /// Maximum distance of a quote to the reference mid as a fraction, 0 disables
+    #[arg(long, env = "VEGAMM_RISK_PRICE_COLLAR")]
+    risk_price_collar: Option<f64>,
+}
+
+impl RiskArgs {
+    pub fn apply(&self, cfg: &mut RiskConfig) {
+        if let Some(v) = self.risk_max_position {
+            cfg.max_position = v;
+        }
+        if let Some(v) = self.risk_max_notional_per_side {
+            cfg.max_notional_per_side = v;
+        }
+        if let Some(v) = self.risk_max_order_size {
+            cfg.max_order_size = v;
+        }
+        if let Some(v) = self.risk_max_live_orders {
+            cfg.max_live_orders = v;
+        }
+        if let Some(v) = self.risk_price_collar {
+            cfg.price_collar = v;
+        }
+    }
+}
+
+/// Checks the state of the market against the limits, returns a description
+/// of the breach when the position or the live orders are over the limits,
+/// which only fills or orders we did not send can cause
+pub fn check_breach(cfg: &RiskConfig, snap: &Snapshot) -> Result<Option<String>, Error> {
+    let (open_volume, _) = snap.position()?;
+    let max_position = decimals::from_f64(cfg.max_position)?;
+    if !max_position.is_zero() && open_volume.abs() > max_position {
+        return Ok(Some(format!(
+            "position {} is over the max position {}",
+            open_volume, max_position
+        )));
+    }
+    if cfg.max_live_orders != 0 && snap.orders.len() > cfg.max_live_orders {
+        return Ok(Some(format!(
+            "{} live orders, over the max of {}",
+            snap.orders.len(),
+            cfg.max_live_orders
+        )));
+    }
+    return Ok(None);
+}
+
+/// Drops the quotes crossing the reference or outside of the price collar and
+/// clips the sizes of the others to the order size, position and notional
+/// limits. Quotes closest to the touch are served first.
+pub fn check_quotes(
+    cfg: &RiskConfig,
+    snap: &Snapshot,
+    mut quotes: Vec<Quote>,
+) -> Result<Vec<Quote>, Error> {
+    let mid = decimals::from_f64(snap.mid())?;
+    let best_bid = decimals::from_f64(snap.best_bid)?;
+    let best_ask = decimals::from_f64(snap.best_ask)?;
+    let collar = decimals::from_f64(cfg.price_collar)?;
+    let max_position = decimals::from_f64(cfg.max_position)?;
+    let max_notional = decimals::from_f64(cfg.max_notional_per_side)?;
+    let max_order_size = decimals::from_f64(cfg.max_order_size)?;
+    let (open_volume, _) = snap.position()?;
+
+    quotes.sort_by(|a, b| match (a.side, b.side) {
+        (Side::Buy, Side::Buy) => b.price.cmp(&a.price),
+        (Side::Buy, _) => Ordering::Less,
+        (_, Side::Buy) => Ordering::Greater,
+        _ => a.price.cmp(&b.price),
+    });
+
+    // room left on each side, buying adds to the position
+    let mut bid_position_room = max_position - open_volume;
+    let mut ask_position_room = max_position + open_volume;
+    let mut bid_notional_room = max_notional;
+    let mut ask_notional_room = max_notional;
+
+    let mut checked = vec![];
+    for mut q in quotes.into_iter() {
+        if q.price <= Decimal::ZERO {
+            warn!("risk: dropping {:?}, price is not positive", q);
+            continue;
+        }
+        let crossed = match q.side {
+            Side::Buy => q.price >= best_ask,
+            _ => q.price <= best_bid,
+        };
+        if crossed {
+            warn!(
+                "risk: dropping {:?}, crossing the reference {}/{}",
+                q, best_bid, best_ask
+            );
+            continue;
+        }
+        if !collar.is_zero() && ((q.price - mid) / mid).abs() > collar {
+            warn!(
+                "risk: dropping {:?}, outside of the {} price collar around {}",
+                q, collar, mid
+            );
+            continue;
+        }
+
+        let (position_room, notional_room) = match q.side {
+            Side::Buy => (&mut bid_position_room, &mut bid_notional_room),
+            _ => (&mut ask_position_room, &mut ask_notional_room),
+        };
+
+        let mut size = q.size;
+        let mut limits = vec![];
+        if !max_order_size.is_zero() && size > max_order_size {
+            size = max_order_size;
+            limits.push("max order size");
+        }
+        if !max_position.is_zero() && size > *position_room {
+            size = (*position_room).max(Decimal::ZERO);
+            limits.push("max position");
+        }
+        if !max_notional.is_zero() && size * q.price > *notional_room {
+            size = (*notional_room / q.price).max(Decimal::ZERO);
+            limits.push("max notional");
+        }
+
+        if size != q.size {
+            warn!(
+                "risk: clipping {:?} to size {} ({})",
+                q,
+                size,
+                limits.join(", ")
+            );
+        }
+        if size.is_zero() {
+            continue;
+        }
+
+        *position_room -= size;
+        *notional_room -= size * q.price;
+        q.size = size;
+        checked.push(q);
+    }
+
+    return Ok(checked);
+}
+
+/// Drops the submissions of the batch furthest from the touch
+/// which would take the market over the live orders limit
+pub fn check_batch(cfg: &RiskConfig, live_orders: usize, batch: &mut BatchMarketInstructions) {
+    if cfg.max_live_orders == 0 {
+        return;
+    }
+
+    let after = (live_orders + batch.submissions.len()).saturating_sub(batch.cancellations.len());
+    if after <= cfg.max_live_orders {
+        return;
+    }
+
+    // submissions are ordered closest to the touch first on each side,
+    // drop from the end of each side alternately
+    let mut excess = after - cfg.max_live_orders;
+    let mut side = Side::Sell;
+    while excess > 0 && !batch.submissions.is_empty() {
+        let pos = batch
+            .submissions
+            .iter()
+            .rposition(|o| o.side == side)
+            .unwrap_or(batch.submissions.len() - 1);
+        let dropped = batch.submissions.remove(pos);
+        warn!(
+            "risk: dropping submission {:?}, the market would have more than {} live orders",
+            dropped, cfg.max_live_orders
+        );
+        excess -= 1;
+        side = match side {
+            Side::Sell => Side::Buy,
+            _ => Side::Sell,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vega_protobufs::vega::Position;
+
+    use crate::decimals::testing::{dec, without_decimals};
+
+    fn quote(side: Side, price: &str, size: &str) -> Quote {
+        return Quote {
+            side,
+            price: dec(price),
+            size: dec(size),
+        };
+    }
+
+    // reference 100/101
+    fn snapshot(open_volume: i64) -> Snapshot {
+        let (market, asset, decimals) = without_decimals();
+        return Snapshot {
+            pubkey: "pubkey".to_string(),
+            decimals,
+            market,
+            asset,
+            position: Some(Position {
+                open_volume,
+                average_entry_price: "100".to_string(),
+                ..Default::default()
+            }),
+            orders: vec![],
+            accounts: vec![],
+            fills: vec![],
+            best_bid: 100.,
+            best_ask: 101.,
+        };
+    }
+
+    fn unlimited() -> RiskConfig {
+        return RiskConfig {
+            max_position: 0.,
+            max_notional_per_side: 0.,
+            max_order_size: 0.,
+            max_live_orders: 0,
+            price_collar: 0.,
+        };
+    }
+
+    #[test]
+    fn crossing_quotes_are_dropped() {
+        let quotes = vec![
+            quote(Side::Buy, "101", "1"),
+            quote(Side::Buy, "100", "1"),
+            quote(Side::Sell, "100", "1"),
+            quote(Side::Sell, "101", "1"),
+        ];
+
+        let checked = check_quotes(&unlimited(), &snapshot(0), quotes).unwrap();
+        assert_eq!(
+            checked,
+            vec![quote(Side::Buy, "100", "1"), quote(Side::Sell, "101", "1")]
+        );
+    }
+
+    #[test]
+    fn quotes_outside_of_the_collar_are_dropped() {
+        let cfg = RiskConfig {
+            price_collar: 0.05,
+            ..unlimited()
+        };
+        let quotes = vec![
+            quote(Side::Buy, "96", "1"),
+            quote(Side::Buy, "95", "1"),
+            quote(Side::Sell, "105", "1"),
+            quote(Side::Sell, "106", "1"),
+        ];
+
+        let checked = check_quotes(&cfg, &snapshot(0), quotes).unwrap();
+        assert_eq!(
+            checked,
+            vec![quote(Side::Buy, "96", "1"), quote(Side::Sell, "105", "1")]
+        );
+    }
+
+    #[test]
+    fn non_positive_prices_are_dropped() {
+        let quotes = vec![quote(Side::Buy, "0", "1"), quote(Side::Buy, "-1", "1")];
+
+        let checked = check_quotes(&unlimited(), &snapshot(0), quotes).unwrap();
+        assert!(checked.is_empty());
+    }
+
+    #[test]
+    fn sizes_are_clipped_to_the_order_size() {
+        let cfg = RiskConfig {
+            max_order_size: 2.,
+            ..unlimited()
+        };
+        let quotes = vec![quote(Side::Buy, "99", "5"), quote(Side::Sell, "102", "1")];
+
+        let checked = check_quotes(&cfg, &snapshot(0), quotes).unwrap();
+        assert_eq!(
+            checked,
+            vec![quote(Side::Buy, "99", "2"), quote(Side::Sell, "102", "1")]
+        );
+    }
+
+    #[test]
+    fn sizes_are_clipped_to_the_position_closest_to_the_touch_first() {
+        let cfg = RiskConfig {
+            max_position: 3.,
+            ..unlimited()
+        };
+        // long 1, so 2 left to buy and 4 to sell
+        let quotes = vec![
+            quote(Side::Buy, "98", "2"),
+            quote(Side::Buy, "99", "1"),
+            quote(Side::Sell, "103", "3"),
+            quote(Side::Sell, "102", "3"),
+        ];
+
+        let checked = check_quotes(&cfg, &snapshot(1), quotes).unwrap();
+        assert_eq!(
+            checked,
+            vec![
+                quote(Side::Buy, "99", "1"),
+                quote(Side::Buy, "98", "1"),
+                quote(Side::Sell, "102", "3"),
+                quote(Side::Sell, "103", "1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_are_clipped_to_the_notional() {
+        let cfg = RiskConfig {
+            max_notional_per_side: 300.,
+            ..unlimited()
+        };
+        let quotes = vec![
+            quote(Side::Buy, "100", "2"),
+            quote(Side::Buy, "80", "2"),
+            quote(Side::Buy, "50", "1"),
+        ];
+
+        let checked = check_quotes(&cfg, &snapshot(0), quotes).unwrap();
+        assert_eq!(
+            checked,
+            vec![quote(Side::Buy, "100", "2"), quote(Side::Buy, "80", "1.25")]
+        );
+    }
+
+    #[test]
+    fn position_over_the_limit_is_a_breach() {
+        let cfg = RiskConfig {
+            max_position: 3.,
+            ..unlimited()
+        };
+        assert!(check_breach(&cfg, &snapshot(3)).unwrap().is_none());
+        assert!(check_breach(&cfg, &snapshot(-4)).unwrap().is_some());
+    }
+}
@@ -0,0 +1,103 @@
+use log::{info, warn};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+use vega_wallet_client::WalletClient;
+
+use crate::{reconciler, vega_store::VegaStore};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why the bot is shutting down
+#[derive(Debug, Clone)]
+pub enum Reason {
+    Signal(&'static str),
+    FeedLoss(Duration),
+    RiskBreach(String),
+}
+
+impl Reason {
+    // critical failures make the process exit with an error
+    pub fn is_critical(&self) -> bool {
+        return !matches!(self, Reason::Signal(_));
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Signal(name) => write!(f, "received {}", name),
+            Reason::FeedLoss(d) => write!(f, "reference feed lost for {:?}", d),
+            Reason::RiskBreach(e) => write!(f, "risk limit breached: {}", e),
+        }
+    }
+}
+
+/// Handle given to the tasks which can decide the bot must stop
+#[derive(Clone)]
+pub struct KillSwitch {
+    tx: mpsc::UnboundedSender<Reason>,
+}
+
+impl KillSwitch {
+    pub fn new() -> (KillSwitch, mpsc::UnboundedReceiver<Reason>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        return (KillSwitch { tx }, rx);
+    }
+
+    pub fn trigger(&self, reason: Reason) {
+        warn!("kill switch triggered: {}", reason);
+        let _ = self.tx.send(reason);
+    }
+}
+
+/// Waits for SIGINT or SIGTERM
+pub async fn signal_received() -> Reason {
+    let mut term = signal(SignalKind::terminate()).expect("could not listen to SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => Reason::Signal("SIGINT"),
+        _ = term.recv() => Reason::Signal("SIGTERM"),
+    }
+}
+
+/// Cancels all our orders on the market until the store does not have
+/// any live order left, returns false if some are still live once the
+/// timeout elapsed
+pub async fn cancel_all_and_wait(
+    clt: &WalletClient,
+    market: &str,
+    store: Arc<Mutex<VegaStore>>,
+    timeout: Duration,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    // the store may not know yet about orders we just submitted so the
+    // cancellation is always sent, then sent again while orders are live
+    // in case some landed after it
+    let mut sent = false;
+    loop {
+        info!("cancelling all orders on {}", market);
+        // a hung wallet must not hold the shutdown past the deadline
+        match time::timeout_at(deadline, clt.send(reconciler::cancel_all(market))).await {
+            Ok(Ok(_)) => sent = true,
+            Ok(Err(e)) => warn!("could not cancel orders: {}", e),
+            Err(_) => warn!("the wallet did not respond to the cancellation"),
+        }
+        time::sleep_until(std::cmp::min(Instant::now() + POLL_INTERVAL, deadline)).await;
+
+        let live = store.lock().unwrap().get_orders().len();
+        if sent && live == 0 {
+            info!("no live orders left on {}", market);
+            return true;
+        }
+        if Instant::now() >= deadline {
+            warn!(
+                "{} orders still live on {} after {:?}",
+                live, market, timeout
+            );
+            return false;
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::strategy_config::StrategyConfig;
+
+/// Price adjustments leaning the quotes out of the current inventory,
+/// all expressed as fractions of the reference price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Skew {
+    // inventory relative to the max position, between -1 and 1
+    pub inventory: f64,
+    // shift applied to both reference prices
+    pub mid: f64,
+    // added to the offset of every bid level
+    pub bid_offset: f64,
+    // added to the offset of every ask level
+    pub ask_offset: f64,
+}
+
+impl Skew {
+    pub fn new(cfg: &StrategyConfig, open_volume: f64) -> Skew {
+        if cfg.max_position <= 0. {
+            return Skew {
+                inventory: 0.,
+                mid: 0.,
+                bid_offset: 0.,
+                ask_offset: 0.,
+            };
+        }
+
+        // when long the mid moves down, the bids widen and the asks
+        // tighten so we are more likely to sell than to buy, and the
+        // other way around when short
+        let q = (open_volume / cfg.max_position).clamp(-1., 1.);
+        return Skew {
+            inventory: q,
+            mid: -q * cfg.mid_skew,
+            bid_offset: q * cfg.spread_skew,
+            ask_offset: -q * cfg.spread_skew,
+        };
+    }
+}
@@ -1,181 +1,477 @@
-use log::info;
-use num_bigint::BigUint;
-use num_traits::cast::FromPrimitive;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use std::error::Error as StdError;
+use std::fmt;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
 use tokio::time;
+use vega_protobufs::datanode::api::v2::AccountBalance;
 use vega_protobufs::vega::{instrument::Product, Market};
-use vega_protobufs::vega::{Asset, Position};
+use vega_protobufs::vega::{Asset, Order, Position, Trade};
+use vega_wallet_client::commands::{BatchMarketInstructions, OrderSubmission, Side};
 use vega_wallet_client::WalletClient;
 
-use crate::{binance_ws::RefPrice, vega_store::VegaStore};
+use crate::{
+    avellaneda::AvellanedaStrategy,
+    control::Control,
+    decimals::{self, Decimals, Rounding},
+    events::{self, Event},
+    health::Health,
+    ladder::LadderStrategy,
+    metrics::Metrics,
+    push::{self, Decision},
+    reconciler,
+    ref_price::{FeedState, RefPrice},
+    risk,
+    shutdown::{KillSwitch, Reason},
+    strategy_config::{QuoteModel, StrategyConfig},
+    vega_store::VegaStore,
+};
+
+/// A quote wanted on the market, in human units
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+/// A read-only view of the store and the reference prices,
+/// taken at the beginning of each strategy run
+pub struct Snapshot {
+    pub pubkey: String,
+    pub market: Market,
+    pub asset: Asset,
+    pub position: Option<Position>,
+    pub orders: Vec<Order>,
+    pub accounts: Vec<AccountBalance>,
+    // our most recent fills, the latest last
+    pub fills: Vec<Trade>,
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub decimals: Decimals,
+}
+
+impl Snapshot {
+    fn new(pubkey: &str, store: &VegaStore, rp: &RefPrice) -> Snapshot {
+        let market = store.get_market();
+        let asset = store.get_settlement_asset();
+        let (best_bid, best_ask) = rp.get();
+        return Snapshot {
+            pubkey: pubkey.to_string(),
+            decimals: Decimals::new(&market, &asset),
+            market,
+            asset,
+            position: store.get_position(),
+            orders: store.get_orders(),
+            accounts: store.get_accounts(),
+            fills: store.get_fills(),
+            best_bid,
+            best_ask,
+        };
+    }
+
+    pub fn mid(&self) -> f64 {
+        return (self.best_bid + self.best_ask) / 2.;
+    }
+
+    // general and margin balances of the pubkey in the settlement asset
+    pub fn balance(&self) -> Result<Decimal, decimals::Error> {
+        let mut balance = Decimal::ZERO;
+        for acc in self.accounts.iter() {
+            if acc.asset == self.asset.id && acc.owner == self.pubkey {
+                balance += self.decimals.from_asset_precision(&acc.balance)?;
+            }
+        }
+        return Ok(balance);
+    }
+
+    // return vol, aep
+    pub fn position(&self) -> Result<(Decimal, Decimal), decimals::Error> {
+        if let Some(p) = &self.position {
+            return Ok((
+                self.decimals
+                    .from_market_position_precision(p.open_volume)?,
+                self.decimals
+                    .from_market_price_precision(&p.average_entry_price)?,
+            ));
+        }
+
+        return Ok((Decimal::ZERO, Decimal::ZERO));
+    }
+}
+
+/// A quoting strategy, turning a snapshot of the market into the quotes
+/// we want to have on the book. Orders are then reconciled and sent by the
+/// runner, strategies never talk to the wallet.
+pub trait Strategy: Send {
+    fn name(&self) -> &'static str;
+
+    // parameters updated at runtime, the model is never changed
+    fn set_config(&mut self, cfg: StrategyConfig);
+
+    fn quotes(&mut self, snap: &Snapshot) -> Result<Vec<Quote>, Error>;
+}
+
+/// Instantiates the strategy selected by the configuration
+pub fn from_config(cfg: &StrategyConfig) -> Box<dyn Strategy> {
+    return match cfg.model {
+        QuoteModel::Ladder => Box::new(LadderStrategy::new(cfg.clone())),
+        QuoteModel::AvellanedaStoikov => Box::new(AvellanedaStrategy::new(cfg.clone())),
+    };
+}
+
+/// Everything a strategy run needs to turn quotes into orders on Vega
+pub struct Runner {
+    pub clt: Arc<WalletClient>,
+    pub pubkey: String,
+    pub market: String,
+    pub store: Arc<Mutex<VegaStore>>,
+    pub rp: Arc<Mutex<RefPrice>>,
+    pub max_price_age: Duration,
+    // the kill switch is triggered once the feed has been down for longer
+    pub max_feed_down: Option<Duration>,
+    pub cfg: StrategyConfig,
+    pub kill: KillSwitch,
+    pub control: Arc<Mutex<Control>>,
+    pub metrics: Arc<Mutex<Metrics>>,
+    pub push: push::Sender,
+    pub health: Arc<Mutex<Health>>,
+}
 
 pub async fn start(
-    clt: WalletClient,
-    pubkey: String,
-    market: String,
-    store: Arc<Mutex<VegaStore>>,
-    rp: Arc<Mutex<RefPrice>>,
+    mut runner: Runner,
+    mut events: events::Receiver,
+    mut shutdown: watch::Receiver<bool>,
 ) {
-    // just loop forever, waiting for user interupt
-    let mut interval = time::interval(Duration::from_secs(5));
+    let mut strategy = from_config(&runner.cfg);
+    info!("starting {} strategy", strategy.name());
+
+    let mut wake = Wake::new(&runner.cfg);
+    let mut events_open = true;
+    // loop until the bot shuts down
+    let mut interval = time::interval(runner.cfg.refresh_interval());
     loop {
         tokio::select! {
-            _ = interval.tick() => {
-                interval.reset();
-                run_strategy(&clt, pubkey.clone(), market.clone(), store.clone(), rp.clone()).await;
+            _ = interval.tick() => {}
+            _ = time::sleep_until(wake.at.unwrap_or_else(time::Instant::now)), if wake.at.is_some() => {
+                info!("requoting after {:?}", wake.reason);
+            }
+            ev = events.recv(), if events_open => {
+                match ev {
+                    Ok(ev) => wake.on_event(ev),
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("strategy missed {} events", n);
+                        wake.arm("missed events".to_string());
+                    }
+                    Err(RecvError::Closed) => events_open = false,
+                }
+                continue;
+            }
+            _ = shutdown.changed() => {
+                info!("strategy stopped");
+                return;
             }
         }
+
+        if let Some(cfg) = runner.control.lock().unwrap().take_update() {
+            info!("applying the strategy parameters updated at runtime");
+            if cfg.refresh_interval_ms != runner.cfg.refresh_interval_ms {
+                interval = time::interval(cfg.refresh_interval());
+            }
+            wake.set_config(&cfg);
+            strategy.set_config(cfg.clone());
+            runner.cfg = cfg;
+        }
+
+        interval.reset();
+        runner.run(strategy.as_mut()).await;
+        runner
+            .health
+            .lock()
+            .unwrap()
+            .strategy_ran(runner.cfg.refresh_interval());
+        wake.ran(runner.rp.lock().unwrap().get());
     }
 }
 
-async fn run_strategy(
-    clt: &WalletClient,
-    pubkey: String,
-    market: String,
-    store: Arc<Mutex<VegaStore>>,
-    rp: Arc<Mutex<RefPrice>>,
-) {
-    info!("executing trading strategy...");
-    let mkt = store.lock().unwrap().get_market();
-    let asset = store.lock().unwrap().get_asset(get_asset(&mkt));
-
-    info!(
-        "updating quotes for {}",
-        mkt.tradable_instrument
-            .as_ref()
-            .unwrap()
-            .instrument
-            .as_ref()
-            .unwrap()
-            .name
-    );
-
-    let d = Decimals::new(&mkt, &asset);
-
-    let (best_bid, best_ask) = rp.lock().unwrap().get();
-    info!(
-        "new reference prices: bestBid({}), bestAsk({})",
-        best_bid, best_ask
-    );
-
-    let (open_volume, aep) =
-        volume_and_average_entry_price(&d, &store.lock().unwrap().get_position());
-
-    let balance = get_pubkey_balance(store.clone(), pubkey.clone(), asset.id.clone(), &d);
-    info!("pubkey balance: {}", balance);
-
-    let bid_volume = balance * 0.5 - open_volume * aep;
-    let offer_volume = balance * 0.5 + open_volume * aep;
-    let notional_exposure = (open_volume * aep).abs();
-    info!(
-        "openvolume({}), entryPrice({}), notionalExposure({})",
-        open_volume, aep, notional_exposure,
-    );
-    info!("bidVolume({}), offerVolume({})", bid_volume, offer_volume);
-
-    use vega_wallet_client::commands::{BatchMarketInstructions, OrderCancellation, Side};
-
-    let mut submissions = get_order_submission(&d, best_bid, Side::Buy, market.clone(), bid_volume);
-    submissions.append(&mut get_order_submission(
-        &d,
-        best_ask,
-        Side::Sell,
-        market.clone(),
-        offer_volume,
-    ));
-    let batch = BatchMarketInstructions {
-        cancellations: vec![OrderCancellation {
-            market_id: market.clone(),
-            order_id: "".to_string(),
-        }],
-        amendments: vec![],
-        submissions,
-    };
+// decides when events should wake the strategy before its next tick
+struct Wake {
+    debounce: Duration,
+    min_interval: Duration,
+    threshold_bps: f64,
+    // reference used by the last quotes
+    last_reference: Option<(f64, f64)>,
+    last_run: time::Instant,
+    at: Option<time::Instant>,
+    reason: Option<String>,
+}
 
-    info!("batch submission: {:?}", batch);
-    clt.send(batch).await.unwrap();
+impl Wake {
+    fn new(cfg: &StrategyConfig) -> Wake {
+        return Wake {
+            debounce: cfg.requote_debounce(),
+            min_interval: cfg.min_requote_interval(),
+            threshold_bps: cfg.requote_threshold_bps,
+            last_reference: None,
+            last_run: time::Instant::now(),
+            at: None,
+            reason: None,
+        };
+    }
+
+    fn set_config(&mut self, cfg: &StrategyConfig) {
+        self.debounce = cfg.requote_debounce();
+        self.min_interval = cfg.min_requote_interval();
+        self.threshold_bps = cfg.requote_threshold_bps;
+    }
+
+    fn on_event(&mut self, ev: Event) {
+        match ev {
+            Event::ReferencePrice { bid, ask } => {
+                let moved = match self.last_reference {
+                    Some((last_bid, last_ask)) => {
+                        let mid = (bid + ask) / 2.;
+                        let last_mid = (last_bid + last_ask) / 2.;
+                        ((mid - last_mid) / last_mid).abs() * 10000.
+                    }
+                    None => f64::INFINITY,
+                };
+                if moved >= self.threshold_bps {
+                    self.arm(format!("reference moved by {:.2}bps", moved));
+                }
+            }
+            ev => self.arm(format!("{:?}", ev)),
+        }
+    }
+
+    // the first event schedules the run, the following ones
+    // until it happens are coalesced into it
+    fn arm(&mut self, reason: String) {
+        if self.at.is_some() {
+            return;
+        }
+        let at = time::Instant::now() + self.debounce;
+        self.at = Some(at.max(self.last_run + self.min_interval));
+        self.reason = Some(reason);
+    }
+
+    fn ran(&mut self, reference: (f64, f64)) {
+        self.last_reference = Some(reference);
+        self.last_run = time::Instant::now();
+        self.at = None;
+        self.reason = None;
+    }
 }
 
-fn get_order_submission(
-    d: &Decimals,
-    ref_price: f64,
-    side: vega_wallet_client::commands::Side,
-    market_id: String,
-    target_volume: f64,
-) -> Vec<vega_wallet_client::commands::OrderSubmission> {
-    use vega_wallet_client::commands::{OrderSubmission, OrderType, Side, TimeInForce};
+impl Runner {
+    async fn run(&self, strategy: &mut dyn Strategy) {
+        info!("executing trading strategy...");
+        if self.control.lock().unwrap().take_cancel_all() {
+            // sent even if the store does not know about any order
+            let batch = reconciler::cancel_all(&self.market);
+            info!("cancelling all orders as requested: {:?}", batch);
+            self.send(batch, "cancel orders").await;
+        }
+        if self.control.lock().unwrap().is_paused() {
+            info!("quoting is paused, pulling quotes");
+            self.pull_quotes("quoting is paused".to_string()).await;
+            return;
+        }
+
+        let feed_state = self.rp.lock().unwrap().get_state();
+        if feed_state != FeedState::Connected {
+            let down_for = self
+                .rp
+                .lock()
+                .unwrap()
+                .get_disconnected_at()
+                .map(|t| t.elapsed());
+            let reason = format!(
+                "reference feed is not connected ({:?}, down for {:?})",
+                feed_state, down_for
+            );
+            warn!("{}, pulling quotes", reason);
+            if let (Some(down_for), Some(max)) = (down_for, self.max_feed_down) {
+                if down_for > max {
+                    self.trigger_kill(Reason::FeedLoss(down_for));
+                    return;
+                }
+            }
+            self.pull_quotes(reason).await;
+            return;
+        }
+
+        if self.rp.lock().unwrap().is_stale(self.max_price_age) {
+            let reason = format!(
+                "reference price is stale (age: {:?}, max: {:?})",
+                self.rp.lock().unwrap().get_age(),
+                self.max_price_age
+            );
+            warn!("{}, pulling quotes", reason);
+            self.pull_quotes(reason).await;
+            return;
+        }
+
+        if !self.store.lock().unwrap().is_connected() {
+            let streams = self.store.lock().unwrap().get_streams();
+            let reason = format!(
+                "vega streams are not all connected ({:?})",
+                streams
+                    .iter()
+                    .map(|(stream, status)| format!("{}: {:?}", stream, status.state))
+                    .collect::<Vec<_>>()
+            );
+            warn!("{}, pulling quotes", reason);
+            self.pull_quotes(reason).await;
+            return;
+        }
+
+        let snap = Snapshot::new(
+            &self.pubkey,
+            &self.store.lock().unwrap(),
+            &self.rp.lock().unwrap(),
+        );
+        match risk::check_breach(&self.cfg.risk, &snap) {
+            Ok(None) => {}
+            Ok(Some(breach)) => {
+                self.trigger_kill(Reason::RiskBreach(breach));
+                return;
+            }
+            Err(e) => warn!("could not check the risk limits: {}", e),
+        }
 
-    let size = target_volume / 5. * ref_price;
+        info!(
+            "updating quotes for {}",
+            snap.market
+                .tradable_instrument
+                .as_ref()
+                .unwrap()
+                .instrument
+                .as_ref()
+                .unwrap()
+                .name
+        );
+        info!(
+            "new reference prices: bestBid({}), bestAsk({})",
+            snap.best_bid, snap.best_ask
+        );
+
+        let desired = match strategy
+            .quotes(&snap)
+            .and_then(|quotes| risk::check_quotes(&self.cfg.risk, &snap, quotes))
+            .and_then(|quotes| get_order_submissions(&snap, &self.cfg, &self.market, quotes))
+        {
+            Ok(desired) => desired,
+            Err(e) => {
+                warn!("could not compute quotes, pulling quotes: {}", e);
+                self.pull_quotes(format!("could not compute quotes: {}", e))
+                    .await;
+                return;
+            }
+        };
+
+        let mut batch = reconciler::reconcile(&self.market, desired, &snap.orders, &self.cfg);
+        risk::check_batch(&self.cfg.risk, snap.orders.len(), &mut batch);
+        if reconciler::is_empty(&batch) {
+            info!("quotes are within tolerance, nothing to update");
+            self.decide(Decision::Hold);
+            return;
+        }
 
-    fn price_buy(ref_price: f64, f: f64) -> f64 {
-        ref_price * (1f64 - (f * 0.002))
+        info!(
+            "batch submission ({} cancellations, {} amendments, {} submissions): {:?}",
+            batch.cancellations.len(),
+            batch.amendments.len(),
+            batch.submissions.len(),
+            batch
+        );
+        self.decide(Decision::Quote {
+            cancellations: batch.cancellations.len(),
+            amendments: batch.amendments.len(),
+            submissions: batch.submissions.len(),
+        });
+        self.send(batch, "update quotes").await;
     }
 
-    fn price_sell(ref_price: f64, f: f64) -> f64 {
-        ref_price * (1f64 + (f * 0.002))
+    async fn pull_quotes(&self, reason: String) {
+        self.decide(Decision::Pull { reason });
+        self.cancel_all().await;
     }
 
-    let price_f: fn(f64, f64) -> f64 = match side {
-        Side::Buy => price_buy,
-        Side::Sell => price_sell,
-        _ => panic!("should never happen"),
-    };
+    fn trigger_kill(&self, reason: Reason) {
+        self.decide(Decision::Kill {
+            reason: reason.to_string(),
+        });
+        self.kill.trigger(reason);
+    }
+
+    fn decide(&self, decision: Decision) {
+        let _ = self.push.send(push::Message::Decision(decision));
+    }
+
+    async fn cancel_all(&self) {
+        if self.store.lock().unwrap().get_orders().is_empty() {
+            return;
+        }
 
-    let mut orders: Vec<OrderSubmission> = vec![];
-    for i in vec![1, 2, 3, 4, 5].into_iter() {
-        let p =
-            BigUint::from_f64(d.to_market_price_precision(price_f(ref_price, i as f64))).unwrap();
+        let batch = reconciler::cancel_all(&self.market);
+        info!("cancelling all orders: {:?}", batch);
+        self.send(batch, "cancel orders").await;
+    }
 
+    async fn send(&self, batch: BatchMarketInstructions, what: &str) {
+        let start = time::Instant::now();
+        self.health.lock().unwrap().wallet_requested();
+        let res = self.clt.send(batch).await;
+        self.metrics
+            .lock()
+            .unwrap()
+            .observe_batch(start.elapsed(), res.is_ok());
+        self.health.lock().unwrap().wallet_responded(res.is_ok());
+        if let Err(e) = res {
+            warn!("could not {}: {}", what, e);
+        }
+    }
+}
+
+// bids are rounded down and asks up so the rounding never
+// brings a quote closer to the reference price
+fn get_order_submissions(
+    snap: &Snapshot,
+    cfg: &StrategyConfig,
+    market_id: &str,
+    quotes: Vec<Quote>,
+) -> Result<Vec<OrderSubmission>, Error> {
+    use vega_wallet_client::commands::{OrderType, TimeInForce};
+
+    let mut orders = vec![];
+    for q in quotes.into_iter() {
+        let rounding = match q.side {
+            Side::Buy => Rounding::Floor,
+            _ => Rounding::Ceil,
+        };
         orders.push(OrderSubmission {
-            market_id: market_id.clone(),
-            price: p.to_string(),
-            size: d.to_market_position_precision(size) as u64,
-            side,
+            market_id: market_id.to_string(),
+            price: snap.decimals.to_market_price_precision(q.price, rounding)?,
+            size: snap
+                .decimals
+                .to_market_position_precision(q.size, Rounding::Floor)?,
+            side: q.side,
             time_in_force: TimeInForce::Gtc,
             expires_at: 0,
             r#type: OrderType::Limit,
-            reference: "VEGA_RUST_MM_SIMPLE".to_string(),
+            reference: cfg.order_reference.clone(),
             pegged_order: None,
         });
     }
 
-    return orders;
-}
-
-fn get_pubkey_balance(
-    store: Arc<Mutex<VegaStore>>,
-    pubkey: String,
-    asset_id: String,
-    d: &Decimals,
-) -> f64 {
-    d.from_asset_precision(store.lock().unwrap().get_accounts().iter().fold(
-        0f64,
-        |balance, acc| {
-            if acc.asset != asset_id || acc.owner != pubkey {
-                balance
-            } else {
-                balance + acc.balance.parse::<f64>().unwrap()
-            }
-        },
-    ))
-}
-
-// return vol, aep
-fn volume_and_average_entry_price(d: &Decimals, pos: &Option<Position>) -> (f64, f64) {
-    if let Some(p) = pos {
-        let vol = p.open_volume as f64;
-        let aep = p.average_entry_price.parse::<f64>().unwrap();
-        return (
-            d.from_market_position_precision(vol),
-            d.from_market_price_precision(aep),
-        );
-    }
-
-    return (0., 0.);
+    return Ok(orders);
 }
 
-fn get_asset(mkt: &Market) -> String {
+pub fn get_asset(mkt: &Market) -> String {
     match mkt
         .clone()
         .tradable_instrument
@@ -189,38 +485,30 @@ fn get_asset(mkt: &Market) -> String {
     }
 }
 
-struct Decimals {
-    position_factor: f64,
-    price_factor: f64,
-    asset_factor: f64,
+#[derive(Debug)]
+pub enum Error {
+    Decimals(decimals::Error),
 }
 
-impl Decimals {
-    fn new(mkt: &Market, asset: &Asset) -> Decimals {
-        return Decimals {
-            position_factor: (10_f64).powf(mkt.position_decimal_places as f64),
-            price_factor: (10_f64).powf(mkt.decimal_places as f64),
-            asset_factor: (10_f64).powf(asset.details.as_ref().unwrap().decimals as f64),
-        };
-    }
-
-    fn from_asset_precision(&self, amount: f64) -> f64 {
-        return amount / self.asset_factor;
-    }
-
-    fn from_market_price_precision(&self, price: f64) -> f64 {
-        return price / self.price_factor;
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "strategy error: {}", self.desc())
     }
+}
 
-    fn from_market_position_precision(&self, position: f64) -> f64 {
-        return position / self.position_factor;
+impl From<decimals::Error> for Error {
+    fn from(error: decimals::Error) -> Self {
+        Error::Decimals(error)
     }
+}
 
-    fn to_market_price_precision(&self, price: f64) -> f64 {
-        return price * self.price_factor;
-    }
+impl StdError for Error {}
 
-    fn to_market_position_precision(&self, position: f64) -> f64 {
-        return position * self.position_factor;
+impl Error {
+    pub fn desc(&self) -> String {
+        use Error::*;
+        match self {
+            Decimals(e) => format!("{}", e),
+        }
     }
 }
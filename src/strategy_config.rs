@@ -0,0 +1,432 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::risk::{RiskArgs, RiskConfig};
+
+/// How the volume of a side is spread across its levels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeDistribution {
+    /// Same size on every level
+    Flat,
+    /// Size growing linearly with the distance to the reference
+    Linear,
+    /// Size multiplied by the size ratio on every level
+    Geometric,
+}
+
+/// How the first level prices are derived from the reference
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteModel {
+    /// Fixed offsets around the reference bid and ask
+    Ladder,
+    /// Reservation price and optimal spread from Avellaneda-Stoikov
+    AvellanedaStoikov,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AvellanedaConfig {
+    // risk aversion, the model prices are in bps of the mid
+    // so the parameters do not depend on the market price
+    pub gamma: f64,
+    // order arrival intensity decay, per bps of the mid
+    pub kappa: f64,
+    // remaining trading time used by the model, rolling
+    pub horizon_secs: f64,
+    pub volatility_half_life_secs: f64,
+    // relative volatility per square root of second used until
+    // the estimate has seen enough prices
+    pub initial_volatility: f64,
+}
+
+impl Default for AvellanedaConfig {
+    fn default() -> Self {
+        return AvellanedaConfig {
+            gamma: 0.1,
+            kappa: 0.1,
+            horizon_secs: 60.,
+            volatility_half_life_secs: 300.,
+            initial_volatility: 0.0001,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrategyConfig {
+    pub model: QuoteModel,
+    // number of orders on each side
+    pub levels: usize,
+    // distance of the first level to the reference price, as a fraction
+    pub first_level_offset: f64,
+    // distance between two levels, as a fraction of the reference price
+    pub level_step: f64,
+    pub size_distribution: SizeDistribution,
+    // only used by the geometric distribution
+    pub size_ratio: f64,
+    // fraction of the balance allocated to each side
+    pub balance_fraction: f64,
+    pub refresh_interval_ms: u64,
+    // events wake the strategy before the refresh interval, no sooner
+    // than the min requote interval after the previous run
+    pub min_requote_interval_ms: u64,
+    // delay letting a burst of events coalesce into a single requote
+    pub requote_debounce_ms: u64,
+    // reference price move since the last quotes waking the strategy
+    pub requote_threshold_bps: f64,
+    pub order_reference: String,
+    // live orders closer than this to the desired price are left alone
+    pub price_tolerance_bps: f64,
+    // live orders closer than this fraction to the desired size are left alone
+    pub size_tolerance: f64,
+    // position, in market units, at which the inventory skew is maximal,
+    // 0 disables the skew
+    pub max_position: f64,
+    // shift of the quotes mid at max position, as a fraction
+    pub mid_skew: f64,
+    // extra offset of the side adding to the position at max position,
+    // removed from the other side, as a fraction
+    pub spread_skew: f64,
+    // only used by the avellaneda-stoikov model
+    pub avellaneda: AvellanedaConfig,
+    pub risk: RiskConfig,
+}
+
+impl Default for StrategyConfig {
+    fn default() -> Self {
+        return StrategyConfig {
+            model: QuoteModel::Ladder,
+            levels: 5,
+            first_level_offset: 0.002,
+            level_step: 0.002,
+            size_distribution: SizeDistribution::Flat,
+            size_ratio: 1.,
+            balance_fraction: 0.5,
+            refresh_interval_ms: 5000,
+            min_requote_interval_ms: 500,
+            requote_debounce_ms: 100,
+            requote_threshold_bps: 5.,
+            order_reference: "VEGA_RUST_MM_SIMPLE".to_string(),
+            price_tolerance_bps: 5.,
+            size_tolerance: 0.1,
+            max_position: 0.,
+            mid_skew: 0.001,
+            spread_skew: 0.001,
+            avellaneda: AvellanedaConfig::default(),
+            risk: RiskConfig::default(),
+        };
+    }
+}
+
+impl StrategyConfig {
+    pub fn from_file(path: &str) -> Result<StrategyConfig, Error> {
+        let content = fs::read_to_string(path)?;
+        return match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&content)?),
+            Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&content)?),
+            _ => Err(Error::UnknownFormat(path.to_string())),
+        };
+    }
+
+    // returns a description of every invalid parameter
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.levels == 0 {
+            errors.push("strategy.levels must be at least 1".to_string());
+        }
+        if self.first_level_offset < 0. || self.first_level_offset >= 1. {
+            errors.push("strategy.first_level_offset must be between 0 and 1".to_string());
+        }
+        if self.level_step < 0. || self.level_offset(self.levels.saturating_sub(1)) >= 1. {
+            errors.push("strategy.level_step must keep every level within 100%".to_string());
+        }
+        if self.size_ratio <= 0. {
+            errors.push("strategy.size_ratio must be positive".to_string());
+        }
+        if self.balance_fraction <= 0. || self.balance_fraction > 1. {
+            errors.push("strategy.balance_fraction must be in ]0, 1]".to_string());
+        }
+        if self.refresh_interval_ms == 0 {
+            errors.push("strategy.refresh_interval_ms must not be 0".to_string());
+        }
+        if self.min_requote_interval_ms > self.refresh_interval_ms {
+            errors.push(
+                "strategy.min_requote_interval_ms must not exceed the refresh interval".to_string(),
+            );
+        }
+        if self.requote_threshold_bps <= 0. {
+            errors.push("strategy.requote_threshold_bps must be positive".to_string());
+        }
+        if self.order_reference.is_empty() {
+            errors.push("strategy.order_reference must not be empty".to_string());
+        }
+        if self.price_tolerance_bps < 0. {
+            errors.push("strategy.price_tolerance_bps must not be negative".to_string());
+        }
+        if self.size_tolerance < 0. {
+            errors.push("strategy.size_tolerance must not be negative".to_string());
+        }
+        if self.max_position < 0. {
+            errors.push("strategy.max_position must not be negative".to_string());
+        }
+        if self.mid_skew < 0. || self.mid_skew >= 1. {
+            errors.push("strategy.mid_skew must be between 0 and 1".to_string());
+        }
+        if self.spread_skew < 0. || self.spread_skew >= 1. {
+            errors.push("strategy.spread_skew must be between 0 and 1".to_string());
+        }
+        if self.avellaneda.gamma <= 0. {
+            errors.push("strategy.avellaneda.gamma must be positive".to_string());
+        }
+        if self.avellaneda.kappa <= 0. {
+            errors.push("strategy.avellaneda.kappa must be positive".to_string());
+        }
+        if self.avellaneda.horizon_secs < 0. {
+            errors.push("strategy.avellaneda.horizon_secs must not be negative".to_string());
+        }
+        if self.avellaneda.volatility_half_life_secs <= 0. {
+            errors
+                .push("strategy.avellaneda.volatility_half_life_secs must be positive".to_string());
+        }
+        if self.avellaneda.initial_volatility < 0. {
+            errors.push("strategy.avellaneda.initial_volatility must not be negative".to_string());
+        }
+        errors.append(&mut self.risk.validate());
+        return errors;
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        return Duration::from_millis(self.refresh_interval_ms);
+    }
+
+    pub fn min_requote_interval(&self) -> Duration {
+        return Duration::from_millis(self.min_requote_interval_ms);
+    }
+
+    pub fn requote_debounce(&self) -> Duration {
+        return Duration::from_millis(self.requote_debounce_ms);
+    }
+
+    // offset of a level from the reference price, as a fraction
+    pub fn level_offset(&self, level: usize) -> f64 {
+        return self.first_level_offset + self.level_step * level as f64;
+    }
+
+    // share of the side volume allocated to each level, summing to 1
+    pub fn level_weights(&self) -> Vec<f64> {
+        let weights = (0..self.levels)
+            .map(|i| match self.size_distribution {
+                SizeDistribution::Flat => 1.,
+                SizeDistribution::Linear => (i + 1) as f64,
+                SizeDistribution::Geometric => self.size_ratio.powi(i as i32),
+            })
+            .collect::<Vec<f64>>();
+        let total = weights.iter().sum::<f64>();
+        return weights.into_iter().map(|w| w / total).collect();
+    }
+}
+
+#[derive(clap::Args)]
+pub struct StrategyArgs {
+    /// A TOML or YAML file with the strategy parameters, replacing the
+    /// strategy section of the configuration file
+    #[arg(long, env = "VEGAMM_STRATEGY_CONFIG")]
+    strategy_config: Option<String>,
+    /// How the first level prices are derived from the reference
+    #[arg(long, env = "VEGAMM_MODEL", value_enum)]
+    model: Option<QuoteModel>,
+    /// Number of orders on each side
+    #[arg(long, env = "VEGAMM_LEVELS")]
+    levels: Option<usize>,
+    /// Distance of the first level to the reference price, as a fraction
+    #[arg(long, env = "VEGAMM_FIRST_LEVEL_OFFSET")]
+    first_level_offset: Option<f64>,
+    /// Distance between two levels, as a fraction of the reference price
+    #[arg(long, env = "VEGAMM_LEVEL_STEP")]
+    level_step: Option<f64>,
+    /// How the volume of a side is spread across its levels
+    #[arg(long, env = "VEGAMM_SIZE_DISTRIBUTION", value_enum)]
+    size_distribution: Option<SizeDistribution>,
+    /// Ratio between the sizes of two levels with the geometric distribution
+    #[arg(long, env = "VEGAMM_SIZE_RATIO")]
+    size_ratio: Option<f64>,
+    /// Fraction of the balance allocated to each side
+    #[arg(long, env = "VEGAMM_BALANCE_FRACTION")]
+    balance_fraction: Option<f64>,
+    /// Interval between two quotes updates in milliseconds
+    #[arg(long, env = "VEGAMM_REFRESH_INTERVAL_MS")]
+    refresh_interval_ms: Option<u64>,
+    /// Minimum interval between two event driven quotes updates in milliseconds
+    #[arg(long, env = "VEGAMM_MIN_REQUOTE_INTERVAL_MS")]
+    min_requote_interval_ms: Option<u64>,
+    /// Delay coalescing a burst of events into a single update in milliseconds
+    #[arg(long, env = "VEGAMM_REQUOTE_DEBOUNCE_MS")]
+    requote_debounce_ms: Option<u64>,
+    /// Reference price move in basis points triggering a quotes update
+    #[arg(long, env = "VEGAMM_REQUOTE_THRESHOLD_BPS")]
+    requote_threshold_bps: Option<f64>,
+    /// Reference set on the submitted orders
+    #[arg(long, env = "VEGAMM_ORDER_REFERENCE")]
+    order_reference: Option<String>,
+    /// Price distance in basis points below which a live order is not amended
+    #[arg(long, env = "VEGAMM_PRICE_TOLERANCE_BPS")]
+    price_tolerance_bps: Option<f64>,
+    /// Relative size difference below which a live order is not amended
+    #[arg(long, env = "VEGAMM_SIZE_TOLERANCE")]
+    size_tolerance: Option<f64>,
+    /// Position at which the inventory skew is maximal, 0 disables the skew
+    #[arg(long, env = "VEGAMM_MAX_POSITION")]
+    max_position: Option<f64>,
+    /// Shift of the quotes mid at max position, as a fraction of the reference price
+    #[arg(long, env = "VEGAMM_MID_SKEW")]
+    mid_skew: Option<f64>,
+    /// Extra offset of the side adding to the position at max position, as a fraction
+    #[arg(long, env = "VEGAMM_SPREAD_SKEW")]
+    spread_skew: Option<f64>,
+    /// Risk aversion of the Avellaneda-Stoikov model
+    #[arg(long, env = "VEGAMM_AS_GAMMA")]
+    as_gamma: Option<f64>,
+    /// Order arrival intensity decay of the Avellaneda-Stoikov model, per bps of the mid
+    #[arg(long, env = "VEGAMM_AS_KAPPA")]
+    as_kappa: Option<f64>,
+    /// Rolling horizon of the Avellaneda-Stoikov model in seconds
+    #[arg(long, env = "VEGAMM_AS_HORIZON_SECS")]
+    as_horizon_secs: Option<f64>,
+    /// Half life of the volatility estimate in seconds
+    #[arg(long, env = "VEGAMM_AS_VOLATILITY_HALF_LIFE_SECS")]
+    as_volatility_half_life_secs: Option<f64>,
+    /// Relative volatility per square root of second used to seed the estimate
+    #[arg(long, env = "VEGAMM_AS_INITIAL_VOLATILITY")]
+    as_initial_volatility: Option<f64>,
+    #[command(flatten)]
+    risk: RiskArgs,
+}
+
+impl StrategyArgs {
+    pub fn apply(&self, cfg: &mut StrategyConfig) -> Result<(), Error> {
+        if let Some(path) = &self.strategy_config {
+            *cfg = StrategyConfig::from_file(path)?;
+        }
+
+        if let Some(v) = self.model {
+            cfg.model = v;
+        }
+        if let Some(v) = self.levels {
+            cfg.levels = v;
+        }
+        if let Some(v) = self.first_level_offset {
+            cfg.first_level_offset = v;
+        }
+        if let Some(v) = self.level_step {
+            cfg.level_step = v;
+        }
+        if let Some(v) = self.size_distribution {
+            cfg.size_distribution = v;
+        }
+        if let Some(v) = self.size_ratio {
+            cfg.size_ratio = v;
+        }
+        if let Some(v) = self.balance_fraction {
+            cfg.balance_fraction = v;
+        }
+        if let Some(v) = self.refresh_interval_ms {
+            cfg.refresh_interval_ms = v;
+        }
+        if let Some(v) = self.min_requote_interval_ms {
+            cfg.min_requote_interval_ms = v;
+        }
+        if let Some(v) = self.requote_debounce_ms {
+            cfg.requote_debounce_ms = v;
+        }
+        if let Some(v) = self.requote_threshold_bps {
+            cfg.requote_threshold_bps = v;
+        }
+        if let Some(v) = &self.order_reference {
+            cfg.order_reference = v.clone();
+        }
+        if let Some(v) = self.price_tolerance_bps {
+            cfg.price_tolerance_bps = v;
+        }
+        if let Some(v) = self.size_tolerance {
+            cfg.size_tolerance = v;
+        }
+        if let Some(v) = self.max_position {
+            cfg.max_position = v;
+        }
+        if let Some(v) = self.mid_skew {
+            cfg.mid_skew = v;
+        }
+        if let Some(v) = self.spread_skew {
+            cfg.spread_skew = v;
+        }
+        if let Some(v) = self.as_gamma {
+            cfg.avellaneda.gamma = v;
+        }
+        if let Some(v) = self.as_kappa {
+            cfg.avellaneda.kappa = v;
+        }
+        if let Some(v) = self.as_horizon_secs {
+            cfg.avellaneda.horizon_secs = v;
+        }
+        if let Some(v) = self.as_volatility_half_life_secs {
+            cfg.avellaneda.volatility_half_life_secs = v;
+        }
+        if let Some(v) = self.as_initial_volatility {
+            cfg.avellaneda.initial_volatility = v;
+        }
+        self.risk.apply(&mut cfg.risk);
+        return Ok(());
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    UnknownFormat(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "strategy config error: {}", self.desc())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(error: toml::de::Error) -> Self {
+        Error::Toml(error)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(error: serde_yaml::Error) -> Self {
+        Error::Yaml(error)
+    }
+}
+
+impl StdError for Error {}
+
+impl Error {
+    pub fn desc(&self) -> String {
+        use Error::*;
+        match self {
+            Io(e) => format!("could not read file: {}", e),
+            Toml(e) => format!("invalid TOML: {}", e),
+            Yaml(e) => format!("invalid YAML: {}", e),
+            UnknownFormat(path) => format!("unknown file format, expected toml or yaml: {}", path),
+        }
+    }
+}
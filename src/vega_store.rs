@@ -1,21 +1,36 @@
-use log::info;
-use std::collections::HashMap;
+use log::{info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error as StdError;
 use std::fmt;
+use std::future::Future;
 use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time;
 use tokio_stream::StreamExt;
 use tonic;
 use vega_protobufs::{
     datanode::api::v2::{
         trading_data_service_client::TradingDataServiceClient, AccountBalance, AccountFilter,
-        GetLatestMarketDataRequest, GetMarketRequest, ListAccountsRequest, ListAssetsRequest,
-        ListOrdersRequest, ListPositionsRequest, ObserveAccountsRequest, ObserveMarketsDataRequest,
-        ObserveOrdersRequest, ObservePositionsRequest,
+        DateRange, GetLatestMarketDataRequest, GetMarketRequest, ListAccountsRequest,
+        ListAssetsRequest, ListOrdersRequest, ListPositionsRequest, ListTradesRequest,
+        ObserveAccountsRequest, ObserveMarketsDataRequest, ObserveOrdersRequest,
+        ObservePositionsRequest, ObserveTradesRequest,
     },
-    vega::{Asset, Market, MarketData, Order, Position},
+    vega::{Asset, Market, MarketData, Order, Position, Trade},
 };
 
+use crate::backoff::Backoff;
+use crate::events::{self, Event};
+use crate::push;
+use crate::ref_price::{ConnectionStatus, FeedState};
+use crate::strategy;
+use crate::views::{AccountView, OrderView, PositionView, TradeView};
+
+// most recent fills kept in the store
+const MAX_FILLS: usize = 1000;
+
 pub struct VegaStore {
+    pubkey: String,
     market: Market,
     market_data: MarketData,
     // key = type+asset+market
@@ -25,6 +40,14 @@ pub struct VegaStore {
     position: Option<Position>,
     // key = asset ID
     assets: HashMap<String, Asset>,
+    // our trades on the market, oldest first
+    fills: VecDeque<Trade>,
+    // vega time the store was created at, in nanoseconds,
+    // earlier trades are accounted for by the initial position
+    created_at: i64,
+    streams: HashMap<Stream, ConnectionStatus>,
+    events: events::Sender,
+    push: push::Sender,
 }
 
 impl VegaStore {
@@ -32,95 +55,36 @@ impl VegaStore {
         clt: &mut TradingDataServiceClient<tonic::transport::Channel>,
         mkt_id: &str,
         pubkey: &str,
+        events: events::Sender,
+        push: push::Sender,
     ) -> Result<VegaStore, Error> {
-        info!("1");
         let mkt_resp = clt
             .get_market(GetMarketRequest {
                 market_id: mkt_id.to_string(),
             })
             .await?;
 
-        info!(
-            "market found: {:?}",
-            mkt_resp.get_ref().market.as_ref().unwrap().clone(),
-        );
+        let market = mkt_resp
+            .into_inner()
+            .market
+            .ok_or(Error::MissingField("market".to_string()))?;
+        info!("market found: {:?}", market);
 
-        let mkt_data_resp = clt
-            .get_latest_market_data(GetLatestMarketDataRequest {
-                market_id: mkt_id.to_string(),
-            })
-            .await?;
+        let market_data = get_latest_market_data(clt, mkt_id).await?;
+        info!("market data found: {:?}", market_data);
 
-        info!(
-            "market data found: {:?}",
-            mkt_data_resp
-                .get_ref()
-                .market_data
-                .as_ref()
-                .unwrap()
-                .clone(),
-        );
-
-        let pos_resp = clt
-            .list_positions(ListPositionsRequest {
-                market_id: mkt_id.to_string(),
-                party_id: pubkey.to_string(),
-                pagination: None,
-            })
-            .await?;
-
-        let position = match &pos_resp.get_ref().positions {
-            Some(p) => match p.edges.len() {
-                0 => None,
-                1 => p.edges[0].node.clone(),
-                _ => unreachable!("cannot have 2 position for the same market"),
-            },
-            None => None,
-        };
-
-        let orders_resp = clt
-            .list_orders(ListOrdersRequest {
-                party_id: Some(pubkey.to_string()),
-                market_id: Some(mkt_id.to_string()),
-                live_only: Some(true),
-                filter: None,
-                date_range: None,
-                reference: None,
-                pagination: None,
-            })
-            .await?;
+        let position = list_position(clt, mkt_id, pubkey).await?;
 
         let mut orders = HashMap::new();
-        for o in orders_resp.get_ref().orders.as_ref().unwrap().edges.iter() {
-            let order = o.node.as_ref().unwrap();
-            orders.insert(order.id.clone(), order.clone());
+        for order in list_orders(clt, mkt_id, pubkey).await?.into_iter() {
+            orders.insert(order.id.clone(), order);
         }
 
-        let accounts_resp = clt
-            .list_accounts(ListAccountsRequest {
-                filter: Some(AccountFilter {
-                    party_ids: vec![pubkey.to_string()],
-                    account_types: vec![],
-                    asset_id: "".to_string(),
-                    market_ids: vec![],
-                }),
-                pagination: None,
-            })
-            .await?;
-
         let mut accounts = HashMap::new();
-        for a in accounts_resp
-            .get_ref()
-            .accounts
-            .as_ref()
-            .unwrap()
-            .edges
-            .iter()
-        {
-            let account = a.node.as_ref().unwrap();
+        for account in list_accounts(clt, pubkey).await?.into_iter() {
             accounts.insert(
                 format!("{}{}{}", account.r#type, account.asset, account.market_id),
-                account.clone(),
+                account,
             );
         }
 
@@ -132,23 +96,40 @@ impl VegaStore {
             .await?;
 
         let mut assets = HashMap::new();
-        for a in assets_resp.get_ref().assets.as_ref().unwrap().edges.iter() {
-            let asset = a.node.as_ref().unwrap();
-            assets.insert(asset.id.clone(), asset.clone());
+        let edges = assets_resp
+            .into_inner()
+            .assets
+            .ok_or(Error::MissingField("assets".to_string()))?
+            .edges;
+        for e in edges.into_iter() {
+            let asset = e.node.ok_or(Error::MissingField("asset".to_string()))?;
+            assets.insert(asset.id.clone(), asset);
+        }
+
+        // the settlement asset is looked up for every conversion
+        let settlement_asset = strategy::get_asset(&market);
+        if !assets.contains_key(&settlement_asset) {
+            return Err(Error::UnknownAsset(settlement_asset));
+        }
+
+        let mut streams = HashMap::new();
+        for stream in Stream::ALL.iter() {
+            streams.insert(*stream, ConnectionStatus::new());
         }
 
         return Ok(VegaStore {
-            market: mkt_resp.get_ref().market.as_ref().unwrap().clone(),
-            market_data: mkt_data_resp
-                .get_ref()
-                .market_data
-                .as_ref()
-                .unwrap()
-                .clone(),
+            pubkey: pubkey.to_string(),
+            market,
             assets,
             position,
             orders,
             accounts,
+            fills: VecDeque::new(),
+            created_at: market_data.timestamp,
+            market_data,
+            streams,
+            events,
+            push,
         });
     }
 
@@ -156,8 +137,13 @@ impl VegaStore {
         return self.market.clone();
     }
 
-    pub fn get_asset(&self, id: String) -> Asset {
-        return self.assets[&id].clone();
+    pub fn get_asset(&self, id: &str) -> Option<Asset> {
+        return self.assets.get(id).cloned();
+    }
+
+    // always known, checked when the store is created
+    pub fn get_settlement_asset(&self) -> Asset {
+        return self.assets[&strategy::get_asset(&self.market)].clone();
     }
 
     pub fn get_market_data(&self) -> MarketData {
@@ -180,14 +166,71 @@ impl VegaStore {
         return self.assets.clone().into_values().collect();
     }
 
+    // most recent last
+    pub fn get_fills(&self) -> Vec<Trade> {
+        return self.fills.iter().cloned().collect();
+    }
+
+    // trades from this time on may be missing from the fills
+    pub fn get_fills_since(&self) -> i64 {
+        return match self.fills.back() {
+            Some(t) => t.timestamp,
+            None => self.created_at,
+        };
+    }
+
+    pub fn get_stream_status(&self, stream: Stream) -> ConnectionStatus {
+        return self.streams[&stream].clone();
+    }
+
+    pub fn get_streams(&self) -> Vec<(Stream, ConnectionStatus)> {
+        return Stream::ALL
+            .iter()
+            .map(|s| (*s, self.streams[s].clone()))
+            .collect();
+    }
+
+    // the store can be trusted only while all the streams are up
+    pub fn is_connected(&self) -> bool {
+        return self
+            .streams
+            .values()
+            .all(|s| s.state == FeedState::Connected);
+    }
+
+    pub fn set_stream_state(&mut self, stream: Stream, state: FeedState) {
+        self.streams.get_mut(&stream).unwrap().set_state(state);
+    }
+
     pub fn save_market_data(&mut self, md: MarketData) {
+        if md.market_trading_mode != self.market_data.market_trading_mode {
+            let _ = self.events.send(Event::TradingMode {
+                mode: md.market_trading_mode,
+            });
+        }
         self.market_data = md;
     }
 
     pub fn save_orders(&mut self, orders: Vec<Order>) {
         use vega_protobufs::vega::order::Status;
         for o in orders.into_iter() {
-            if Status::from_i32(o.status).unwrap() != Status::Active {
+            // amendments change the size and the remaining by the same
+            // amount, so only a trade changes their difference
+            let traded_before = self
+                .orders
+                .get(&o.id)
+                .map(|prev| prev.size.saturating_sub(prev.remaining))
+                .unwrap_or(0);
+            let traded = o.size.saturating_sub(o.remaining);
+            if traded > traded_before {
+                let _ = self.events.send(Event::Fill {
+                    order_id: o.id.clone(),
+                    size: traded - traded_before,
+                });
+            }
+
+            let _ = self.push.send(push::Message::Order(OrderView::from(&o)));
+            if Status::from_i32(o.status) != Some(Status::Active) {
                 self.orders.remove(&o.id);
                 continue;
             }
@@ -195,185 +238,565 @@ impl VegaStore {
         }
     }
 
+    // replace the live orders with a snapshot, the ones missing
+    // from it were closed while the stream was down
+    pub fn reset_orders(&mut self, orders: Vec<Order>) {
+        let live: HashSet<&String> = orders.iter().map(|o| &o.id).collect();
+        self.orders.retain(|id, _| live.contains(id));
+        self.save_orders(orders);
+    }
+
     pub fn save_positions(&mut self, positions: Vec<Position>) {
         for p in positions.into_iter() {
+            let open_volume = self.position.as_ref().map(|p| p.open_volume);
+            if open_volume != Some(p.open_volume) {
+                let _ = self.events.send(Event::Position {
+                    open_volume: p.open_volume,
+                });
+            }
+            let _ = self
+                .push
+                .send(push::Message::Position(PositionView::from(&p)));
             self.position = Some(p);
         }
     }
 
+    // replace the position with a snapshot, None once it was closed
+    // while the stream was down
+    pub fn reset_position(&mut self, position: Option<Position>) {
+        match position {
+            Some(p) => self.save_positions(vec![p]),
+            None => {
+                if self.position.take().is_some() {
+                    let _ = self.events.send(Event::Position { open_volume: 0 });
+                }
+            }
+        }
+    }
+
+    pub fn save_trades(&mut self, trades: Vec<Trade>) {
+        for t in trades.into_iter() {
+            if t.buyer != self.pubkey && t.seller != self.pubkey {
+                continue;
+            }
+            // trades can be streamed again after a reconnection
+            if self.fills.iter().any(|f| f.id == t.id) {
+                continue;
+            }
+            let _ = self.push.send(push::Message::Fill(TradeView::from(&t)));
+            self.fills.push_back(t);
+            if self.fills.len() > MAX_FILLS {
+                self.fills.pop_front();
+            }
+        }
+    }
+
     pub fn save_accounts(&mut self, accounts: Vec<AccountBalance>) {
         for a in accounts.into_iter() {
+            let _ = self
+                .push
+                .send(push::Message::Account(AccountView::from(&a)));
             self.accounts
                 .insert(format!("{}{}{}", a.r#type, a.asset, a.market_id), a);
         }
     }
 }
 
+/// The data-node streams maintained by the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stream {
+    Orders,
+    Positions,
+    Accounts,
+    MarketData,
+    Trades,
+}
+
+impl Stream {
+    pub const ALL: [Stream; 5] = [
+        Stream::Orders,
+        Stream::Positions,
+        Stream::Accounts,
+        Stream::MarketData,
+        Stream::Trades,
+    ];
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stream::Orders => write!(f, "orders"),
+            Stream::Positions => write!(f, "positions"),
+            Stream::Accounts => write!(f, "accounts"),
+            Stream::MarketData => write!(f, "market data"),
+            Stream::Trades => write!(f, "trades"),
+        }
+    }
+}
+
+// returns the tasks maintaining each stream
 pub fn update_forever(
     store: Arc<Mutex<VegaStore>>,
     clt: TradingDataServiceClient<tonic::transport::Channel>,
     market: &str,
     pubkey: &str,
-) {
-    tokio::spawn(update_orders_forever(
-        store.clone(),
-        clt.clone(),
-        market.to_string(),
-        pubkey.to_string(),
-    ));
-    tokio::spawn(update_market_data_forever(
-        store.clone(),
-        clt.clone(),
-        market.to_string(),
-    ));
-    tokio::spawn(update_position_forever(
-        store.clone(),
-        clt.clone(),
-        market.to_string(),
-        pubkey.to_string(),
-    ));
-    tokio::spawn(update_accounts_forever(
-        store.clone(),
-        clt.clone(),
-        pubkey.to_string(),
-    ));
+) -> Vec<(Stream, JoinHandle<()>)> {
+    return vec![
+        (
+            Stream::Trades,
+            tokio::spawn(update_trades_forever(
+                store.clone(),
+                clt.clone(),
+                market.to_string(),
+                pubkey.to_string(),
+            )),
+        ),
+        (
+            Stream::Orders,
+            tokio::spawn(update_orders_forever(
+                store.clone(),
+                clt.clone(),
+                market.to_string(),
+                pubkey.to_string(),
+            )),
+        ),
+        (
+            Stream::MarketData,
+            tokio::spawn(update_market_data_forever(
+                store.clone(),
+                clt.clone(),
+                market.to_string(),
+            )),
+        ),
+        (
+            Stream::Positions,
+            tokio::spawn(update_position_forever(
+                store.clone(),
+                clt.clone(),
+                market.to_string(),
+                pubkey.to_string(),
+            )),
+        ),
+        (
+            Stream::Accounts,
+            tokio::spawn(update_accounts_forever(
+                store.clone(),
+                clt.clone(),
+                pubkey.to_string(),
+            )),
+        ),
+    ];
+}
+
+// keep a stream subscribed, resubscribing with a backoff when it fails
+// or ends, the sessions after the first one start with a snapshot to
+// heal what was missed while the stream was down
+async fn supervise<F, Fut>(store: Arc<Mutex<VegaStore>>, stream: Stream, mut session: F)
+where
+    F: FnMut(bool) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let mut backoff = Backoff::reconnect();
+    let mut resnapshot = false;
+    loop {
+        info!("starting {} stream...", stream);
+        match session(resnapshot).await {
+            Ok(()) => warn!("{} stream ended", stream),
+            Err(e) => warn!("{} stream error: {}", stream, e),
+        }
+
+        let was_connected =
+            store.lock().unwrap().get_stream_status(stream).state == FeedState::Connected;
+        store
+            .lock()
+            .unwrap()
+            .set_stream_state(stream, FeedState::Disconnected);
+        if was_connected {
+            backoff.reset();
+        }
+        resnapshot = true;
+
+        let delay = backoff.next();
+        info!("resubscribing to the {} stream in {:?}", stream, delay);
+        time::sleep(delay).await;
+        store
+            .lock()
+            .unwrap()
+            .set_stream_state(stream, FeedState::Connecting);
+    }
 }
 
 async fn update_accounts_forever(
     store: Arc<Mutex<VegaStore>>,
-    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    clt: TradingDataServiceClient<tonic::transport::Channel>,
     pubkey: String,
 ) {
+    supervise(store.clone(), Stream::Accounts, |resnapshot| {
+        run_accounts(store.clone(), clt.clone(), pubkey.clone(), resnapshot)
+    })
+    .await;
+}
+
+async fn run_accounts(
+    store: Arc<Mutex<VegaStore>>,
+    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    pubkey: String,
+    resnapshot: bool,
+) -> Result<(), Error> {
     use vega_protobufs::datanode::api::v2::observe_accounts_response::Response;
 
-    info!("starting accounts stream...");
-    let mut stream = match clt
+    let mut stream = clt
         .observe_accounts(ObserveAccountsRequest {
-            party_id: pubkey,
+            party_id: pubkey.clone(),
             ..Default::default()
         })
-        .await
-    {
-        Ok(s) => s.into_inner(),
-        Err(e) => panic!("{:?}", e),
-    };
+        .await?
+        .into_inner();
+    if resnapshot {
+        let accounts = list_accounts(&mut clt, &pubkey).await?;
+        store.lock().unwrap().save_accounts(accounts);
+    }
+    store
+        .lock()
+        .unwrap()
+        .set_stream_state(Stream::Accounts, FeedState::Connected);
 
     while let Some(item) = stream.next().await {
-        match item {
-            Ok(resp) => match resp.response {
-                Some(r) => match r {
-                    Response::Snapshot(o) => {
-                        store.lock().unwrap().save_accounts(o.accounts.clone())
-                    }
-                    Response::Updates(o) => store.lock().unwrap().save_accounts(o.accounts.clone()),
-                },
-                _ => {}
-            },
-            _ => {}
+        match item?.response {
+            Some(Response::Snapshot(o)) => store.lock().unwrap().save_accounts(o.accounts),
+            Some(Response::Updates(o)) => store.lock().unwrap().save_accounts(o.accounts),
+            None => {}
         }
     }
+    return Err(Error::StreamClosed);
 }
 
-async fn update_orders_forever(
+async fn update_trades_forever(
+    store: Arc<Mutex<VegaStore>>,
+    clt: TradingDataServiceClient<tonic::transport::Channel>,
+    market: String,
+    pubkey: String,
+) {
+    supervise(store.clone(), Stream::Trades, |resnapshot| {
+        run_trades(
+            store.clone(),
+            clt.clone(),
+            market.clone(),
+            pubkey.clone(),
+            resnapshot,
+        )
+    })
+    .await;
+}
+
+async fn run_trades(
     store: Arc<Mutex<VegaStore>>,
     mut clt: TradingDataServiceClient<tonic::transport::Channel>,
     market: String,
     pubkey: String,
+    resnapshot: bool,
+) -> Result<(), Error> {
+    let mut stream = clt
+        .observe_trades(ObserveTradesRequest {
+            market_id: Some(market.clone()),
+            party_id: Some(pubkey.clone()),
+        })
+        .await?
+        .into_inner();
+    // recover the fills missed while the stream was down,
+    // the ones already stored are skipped
+    if resnapshot {
+        let since = store.lock().unwrap().get_fills_since();
+        let trades = list_trades(&mut clt, &market, &pubkey, since).await?;
+        store.lock().unwrap().save_trades(trades);
+    }
+    store
+        .lock()
+        .unwrap()
+        .set_stream_state(Stream::Trades, FeedState::Connected);
+
+    while let Some(item) = stream.next().await {
+        store.lock().unwrap().save_trades(item?.trades);
+    }
+    return Err(Error::StreamClosed);
+}
+
+async fn update_orders_forever(
+    store: Arc<Mutex<VegaStore>>,
+    clt: TradingDataServiceClient<tonic::transport::Channel>,
+    market: String,
+    pubkey: String,
 ) {
+    supervise(store.clone(), Stream::Orders, |resnapshot| {
+        run_orders(
+            store.clone(),
+            clt.clone(),
+            market.clone(),
+            pubkey.clone(),
+            resnapshot,
+        )
+    })
+    .await;
+}
+
+async fn run_orders(
+    store: Arc<Mutex<VegaStore>>,
+    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    market: String,
+    pubkey: String,
+    resnapshot: bool,
+) -> Result<(), Error> {
     use vega_protobufs::datanode::api::v2::observe_orders_response::Response;
 
-    info!("starting orders stream...");
-    let mut stream = match clt
+    let mut stream = clt
         .observe_orders(ObserveOrdersRequest {
-            party_id: Some(pubkey),
-            market_id: Some(market),
+            party_id: Some(pubkey.clone()),
+            market_id: Some(market.clone()),
             exclude_liquidity: Some(false),
         })
-        .await
-    {
-        Ok(s) => s.into_inner(),
-        Err(e) => panic!("{:?}", e),
-    };
+        .await?
+        .into_inner();
+    if resnapshot {
+        let orders = list_orders(&mut clt, &market, &pubkey).await?;
+        store.lock().unwrap().reset_orders(orders);
+    }
+    store
+        .lock()
+        .unwrap()
+        .set_stream_state(Stream::Orders, FeedState::Connected);
 
     while let Some(item) = stream.next().await {
-        match item {
-            Ok(resp) => match resp.response {
-                Some(r) => match r {
-                    Response::Snapshot(o) => store.lock().unwrap().save_orders(o.orders.clone()),
-                    Response::Updates(o) => store.lock().unwrap().save_orders(o.orders.clone()),
-                },
-                _ => {}
-            },
-            _ => {}
+        match item?.response {
+            Some(Response::Snapshot(o)) => store.lock().unwrap().save_orders(o.orders),
+            Some(Response::Updates(o)) => store.lock().unwrap().save_orders(o.orders),
+            None => {}
         }
     }
+    return Err(Error::StreamClosed);
 }
 
 async fn update_position_forever(
     store: Arc<Mutex<VegaStore>>,
-    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    clt: TradingDataServiceClient<tonic::transport::Channel>,
     market: String,
     pubkey: String,
 ) {
+    supervise(store.clone(), Stream::Positions, |resnapshot| {
+        run_positions(
+            store.clone(),
+            clt.clone(),
+            market.clone(),
+            pubkey.clone(),
+            resnapshot,
+        )
+    })
+    .await;
+}
+
+async fn run_positions(
+    store: Arc<Mutex<VegaStore>>,
+    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    market: String,
+    pubkey: String,
+    resnapshot: bool,
+) -> Result<(), Error> {
     use vega_protobufs::datanode::api::v2::observe_positions_response::Response;
-    info!("starting positions stream...");
-    let mut stream = match clt
+
+    let mut stream = clt
         .observe_positions(ObservePositionsRequest {
-            party_id: Some(pubkey),
-            market_id: Some(market),
+            party_id: Some(pubkey.clone()),
+            market_id: Some(market.clone()),
         })
-        .await
-    {
-        Ok(s) => s.into_inner(),
-        Err(e) => panic!("{:?}", e),
-    };
+        .await?
+        .into_inner();
+    if resnapshot {
+        let position = list_position(&mut clt, &market, &pubkey).await?;
+        store.lock().unwrap().reset_position(position);
+    }
+    store
+        .lock()
+        .unwrap()
+        .set_stream_state(Stream::Positions, FeedState::Connected);
 
     while let Some(item) = stream.next().await {
-        match item {
-            Ok(resp) => match resp.response {
-                Some(r) => match r {
-                    Response::Snapshot(o) => {
-                        store.lock().unwrap().save_positions(o.positions.clone())
-                    }
-                    Response::Updates(o) => {
-                        store.lock().unwrap().save_positions(o.positions.clone())
-                    }
-                },
-                _ => {}
-            },
-            _ => {}
+        match item?.response {
+            Some(Response::Snapshot(o)) => store.lock().unwrap().save_positions(o.positions),
+            Some(Response::Updates(o)) => store.lock().unwrap().save_positions(o.positions),
+            None => {}
         }
     }
+    return Err(Error::StreamClosed);
 }
 
 async fn update_market_data_forever(
     store: Arc<Mutex<VegaStore>>,
-    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    clt: TradingDataServiceClient<tonic::transport::Channel>,
     market: String,
 ) {
-    info!("starting market data stream...");
-    let mut stream = match clt
+    supervise(store.clone(), Stream::MarketData, |resnapshot| {
+        run_market_data(store.clone(), clt.clone(), market.clone(), resnapshot)
+    })
+    .await;
+}
+
+async fn run_market_data(
+    store: Arc<Mutex<VegaStore>>,
+    mut clt: TradingDataServiceClient<tonic::transport::Channel>,
+    market: String,
+    resnapshot: bool,
+) -> Result<(), Error> {
+    let mut stream = clt
         .observe_markets_data(ObserveMarketsDataRequest {
-            market_ids: vec![market],
+            market_ids: vec![market.clone()],
         })
-        .await
-    {
-        Ok(s) => s.into_inner(),
-        Err(e) => panic!("{:?}", e),
-    };
+        .await?
+        .into_inner();
+    if resnapshot {
+        let md = get_latest_market_data(&mut clt, &market).await?;
+        store.lock().unwrap().save_market_data(md);
+    }
+    store
+        .lock()
+        .unwrap()
+        .set_stream_state(Stream::MarketData, FeedState::Connected);
 
     while let Some(item) = stream.next().await {
-        for md in item.unwrap().market_data.iter() {
+        for md in item?.market_data.into_iter() {
             info!("received market data: {:?}", md);
-            store.lock().unwrap().save_market_data(md.clone())
+            store.lock().unwrap().save_market_data(md)
         }
     }
+    return Err(Error::StreamClosed);
+}
+
+async fn get_latest_market_data(
+    clt: &mut TradingDataServiceClient<tonic::transport::Channel>,
+    market: &str,
+) -> Result<MarketData, Error> {
+    let resp = clt
+        .get_latest_market_data(GetLatestMarketDataRequest {
+            market_id: market.to_string(),
+        })
+        .await?;
+    return resp
+        .into_inner()
+        .market_data
+        .ok_or(Error::MissingField("market data".to_string()));
+}
+
+async fn list_position(
+    clt: &mut TradingDataServiceClient<tonic::transport::Channel>,
+    market: &str,
+    pubkey: &str,
+) -> Result<Option<Position>, Error> {
+    let resp = clt
+        .list_positions(ListPositionsRequest {
+            market_id: market.to_string(),
+            party_id: pubkey.to_string(),
+            pagination: None,
+        })
+        .await?;
+
+    return Ok(match &resp.get_ref().positions {
+        Some(p) => match p.edges.len() {
+            0 => None,
+            1 => p.edges[0].node.clone(),
+            _ => unreachable!("cannot have 2 position for the same market"),
+        },
+        None => None,
+    });
+}
+
+async fn list_trades(
+    clt: &mut TradingDataServiceClient<tonic::transport::Channel>,
+    market: &str,
+    pubkey: &str,
+    since: i64,
+) -> Result<Vec<Trade>, Error> {
+    let resp = clt
+        .list_trades(ListTradesRequest {
+            market_id: Some(market.to_string()),
+            order_id: None,
+            party_id: Some(pubkey.to_string()),
+            pagination: None,
+            date_range: Some(DateRange {
+                start_timestamp: Some(since),
+                end_timestamp: None,
+            }),
+        })
+        .await?;
+
+    let mut trades: Vec<Trade> = match &resp.get_ref().trades {
+        Some(t) => t.edges.iter().filter_map(|e| e.node.clone()).collect(),
+        None => vec![],
+    };
+    // the fills are kept oldest first
+    trades.sort_by_key(|t| t.timestamp);
+    return Ok(trades);
+}
+
+async fn list_orders(
+    clt: &mut TradingDataServiceClient<tonic::transport::Channel>,
+    market: &str,
+    pubkey: &str,
+) -> Result<Vec<Order>, Error> {
+    let resp = clt
+        .list_orders(ListOrdersRequest {
+            party_id: Some(pubkey.to_string()),
+            market_id: Some(market.to_string()),
+            live_only: Some(true),
+            filter: None,
+            date_range: None,
+            reference: None,
+            pagination: None,
+        })
+        .await?;
+
+    let edges = resp
+        .into_inner()
+        .orders
+        .ok_or(Error::MissingField("orders".to_string()))?
+        .edges;
+    return edges
+        .into_iter()
+        .map(|e| e.node.ok_or(Error::MissingField("order".to_string())))
+        .collect();
+}
+
+async fn list_accounts(
+    clt: &mut TradingDataServiceClient<tonic::transport::Channel>,
+    pubkey: &str,
+) -> Result<Vec<AccountBalance>, Error> {
+    let resp = clt
+        .list_accounts(ListAccountsRequest {
+            filter: Some(AccountFilter {
+                party_ids: vec![pubkey.to_string()],
+                account_types: vec![],
+                asset_id: "".to_string(),
+                market_ids: vec![],
+            }),
+            pagination: None,
+        })
+        .await?;
+
+    let edges = resp
+        .into_inner()
+        .accounts
+        .ok_or(Error::MissingField("accounts".to_string()))?
+        .edges;
+    return edges
+        .into_iter()
+        .map(|e| e.node.ok_or(Error::MissingField("account".to_string())))
+        .collect();
 }
 
 #[derive(Debug)]
 pub enum Error {
     GrpcTransportError(tonic::transport::Error),
     GrpcError(tonic::Status),
+    StreamClosed,
+    UnknownAsset(String),
+    MissingField(String),
 }
 
 impl fmt::Display for Error {
@@ -401,6 +824,9 @@ impl Error {
         match self {
             GrpcTransportError(e) => format!("GRPC transport error: {}", e),
             GrpcError(e) => format!("GRPC error: {}", e),
+            StreamClosed => format!("stream closed by the data node"),
+            UnknownAsset(id) => format!("asset not found on the data node: {}", id),
+            MissingField(what) => format!("{} missing from the data node response", what),
         }
     }
 }
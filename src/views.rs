@@ -0,0 +1,279 @@
+use serde::Serialize;
+use std::fmt::Debug;
+use vega_protobufs::datanode::api::v2::AccountBalance;
+use vega_protobufs::vega::{
+    instrument::Product, market, order, AccountType, Asset, Market, MarketData, Order, Position,
+    Side, Trade,
+};
+
+use crate::ref_price::{ConnectionStatus, FeedState, RefPrice};
+use crate::vega_store::Stream;
+
+// amounts, prices and sizes are kept in the precision they have on Vega,
+// the decimals to scale them are given by the market and asset views
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderView {
+    pub id: String,
+    pub market_id: String,
+    pub party_id: String,
+    pub side: String,
+    pub price: String,
+    pub size: u64,
+    pub remaining: u64,
+    pub time_in_force: String,
+    pub r#type: String,
+    pub status: String,
+    pub reference: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub expires_at: i64,
+    pub version: u64,
+}
+
+impl From<&Order> for OrderView {
+    fn from(o: &Order) -> Self {
+        return OrderView {
+            id: o.id.clone(),
+            market_id: o.market_id.clone(),
+            party_id: o.party_id.clone(),
+            side: enum_name(Side::from_i32(o.side)),
+            price: o.price.clone(),
+            size: o.size,
+            remaining: o.remaining,
+            time_in_force: enum_name(order::TimeInForce::from_i32(o.time_in_force)),
+            r#type: enum_name(order::Type::from_i32(o.r#type)),
+            status: enum_name(order::Status::from_i32(o.status)),
+            reference: o.reference.clone(),
+            created_at: o.created_at,
+            updated_at: o.updated_at,
+            expires_at: o.expires_at,
+            version: o.version,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionView {
+    pub market_id: String,
+    pub party_id: String,
+    pub open_volume: i64,
+    pub average_entry_price: String,
+    pub realised_pnl: String,
+    pub unrealised_pnl: String,
+    pub updated_at: i64,
+}
+
+impl From<&Position> for PositionView {
+    fn from(p: &Position) -> Self {
+        return PositionView {
+            market_id: p.market_id.clone(),
+            party_id: p.party_id.clone(),
+            open_volume: p.open_volume,
+            average_entry_price: p.average_entry_price.clone(),
+            realised_pnl: p.realised_pnl.clone(),
+            unrealised_pnl: p.unrealised_pnl.clone(),
+            updated_at: p.updated_at,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountView {
+    pub owner: String,
+    pub r#type: String,
+    pub asset: String,
+    pub market_id: String,
+    pub balance: String,
+}
+
+impl From<&AccountBalance> for AccountView {
+    fn from(a: &AccountBalance) -> Self {
+        return AccountView {
+            owner: a.owner.clone(),
+            r#type: enum_name(AccountType::from_i32(a.r#type)),
+            asset: a.asset.clone(),
+            market_id: a.market_id.clone(),
+            balance: a.balance.clone(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketView {
+    pub id: String,
+    pub name: String,
+    pub code: String,
+    pub settlement_asset: String,
+    pub decimal_places: u64,
+    pub position_decimal_places: i64,
+    pub state: String,
+    pub trading_mode: String,
+}
+
+impl From<&Market> for MarketView {
+    fn from(m: &Market) -> Self {
+        let instrument = m
+            .tradable_instrument
+            .as_ref()
+            .and_then(|t| t.instrument.as_ref());
+        let settlement_asset = match instrument.and_then(|i| i.product.as_ref()) {
+            Some(Product::Future(f)) => f.settlement_asset.clone(),
+            None => "".to_string(),
+        };
+        return MarketView {
+            id: m.id.clone(),
+            name: instrument.map(|i| i.name.clone()).unwrap_or_default(),
+            code: instrument.map(|i| i.code.clone()).unwrap_or_default(),
+            settlement_asset,
+            decimal_places: m.decimal_places,
+            position_decimal_places: m.position_decimal_places,
+            state: enum_name(market::State::from_i32(m.state)),
+            trading_mode: enum_name(market::TradingMode::from_i32(m.trading_mode)),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketDataView {
+    pub market: String,
+    pub mark_price: String,
+    pub best_bid_price: String,
+    pub best_bid_volume: u64,
+    pub best_offer_price: String,
+    pub best_offer_volume: u64,
+    pub mid_price: String,
+    pub open_interest: u64,
+    pub trading_mode: String,
+    pub timestamp: i64,
+}
+
+impl From<&MarketData> for MarketDataView {
+    fn from(md: &MarketData) -> Self {
+        return MarketDataView {
+            market: md.market.clone(),
+            mark_price: md.mark_price.clone(),
+            best_bid_price: md.best_bid_price.clone(),
+            best_bid_volume: md.best_bid_volume,
+            best_offer_price: md.best_offer_price.clone(),
+            best_offer_volume: md.best_offer_volume,
+            mid_price: md.mid_price.clone(),
+            open_interest: md.open_interest,
+            trading_mode: enum_name(market::TradingMode::from_i32(md.market_trading_mode)),
+            timestamp: md.timestamp,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetView {
+    pub id: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u64,
+    pub quantum: String,
+}
+
+impl From<&Asset> for AssetView {
+    fn from(a: &Asset) -> Self {
+        let details = a.details.clone().unwrap_or_default();
+        return AssetView {
+            id: a.id.clone(),
+            name: details.name,
+            symbol: details.symbol,
+            decimals: details.decimals,
+            quantum: details.quantum,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TradeView {
+    pub id: String,
+    pub market_id: String,
+    pub price: String,
+    pub size: u64,
+    pub buyer: String,
+    pub seller: String,
+    pub aggressor: String,
+    pub buy_order: String,
+    pub sell_order: String,
+    pub timestamp: i64,
+}
+
+impl From<&Trade> for TradeView {
+    fn from(t: &Trade) -> Self {
+        return TradeView {
+            id: t.id.clone(),
+            market_id: t.market_id.clone(),
+            price: t.price.clone(),
+            size: t.size,
+            buyer: t.buyer.clone(),
+            seller: t.seller.clone(),
+            aggressor: enum_name(Side::from_i32(t.aggressor)),
+            buy_order: t.buy_order.clone(),
+            sell_order: t.sell_order.clone(),
+            timestamp: t.timestamp,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamView {
+    pub stream: String,
+    pub state: String,
+    pub reconnects: u64,
+    // None while the stream is connected
+    pub disconnected_for_ms: Option<u128>,
+}
+
+impl From<&(Stream, ConnectionStatus)> for StreamView {
+    fn from((stream, status): &(Stream, ConnectionStatus)) -> Self {
+        return StreamView {
+            stream: stream.to_string(),
+            state: format!("{:?}", status.state),
+            reconnects: status.reconnects,
+            disconnected_for_ms: match status.disconnected_at {
+                Some(t) if status.state != FeedState::Connected => Some(t.elapsed().as_millis()),
+                _ => None,
+            },
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefPriceView {
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub best_bid_volume: f64,
+    pub best_ask_volume: f64,
+    pub event_time: u64,
+    pub age_ms: Option<u128>,
+    pub feed_state: String,
+    pub feed_reconnects: u64,
+}
+
+impl From<&RefPrice> for RefPriceView {
+    fn from(rp: &RefPrice) -> Self {
+        let (best_bid, best_ask) = rp.get();
+        let (best_bid_volume, best_ask_volume) = rp.get_volumes();
+        return RefPriceView {
+            best_bid,
+            best_ask,
+            best_bid_volume,
+            best_ask_volume,
+            event_time: rp.get_event_time(),
+            age_ms: rp.get_age().map(|a| a.as_millis()),
+            feed_state: format!("{:?}", rp.get_state()),
+            feed_reconnects: rp.get_reconnects(),
+        };
+    }
+}
+
+// name of a protobuf enum variant, the protobufs only carry their value
+fn enum_name<E: Debug>(v: Option<E>) -> String {
+    return match v {
+        Some(v) => format!("{:?}", v),
+        None => "Unknown".to_string(),
+    };
+}